@@ -0,0 +1,234 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::AppTray;
+use crate::i18n::Text;
+
+// Output formats of the status bar mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BarFormat {
+    /// JSON lines for Waybar custom modules
+    Waybar,
+    /// Plain text lines for Polybar and i3blocks
+    Text,
+}
+
+// Line of a Waybar custom module with `"return-type": "json"`.
+#[derive(Debug, Serialize)]
+struct WaybarLine {
+    text: String,
+    tooltip: String,
+    class: &'static str,
+    percentage: u32,
+}
+
+#[derive(Debug)]
+struct BarState {
+    tray: AppTray,
+    last_line: String,
+}
+
+// Prints the tray state as status bar lines, for desktops without a
+// StatusNotifier host. A new line is printed every time the output changes.
+#[derive(Debug, Clone)]
+pub struct BarOutput {
+    state: Arc<Mutex<BarState>>,
+    format: BarFormat,
+}
+
+impl BarOutput {
+    pub fn new(tray: AppTray, format: BarFormat) -> Self {
+        let output = Self {
+            state: Arc::new(Mutex::new(BarState {
+                tray,
+                last_line: String::new(),
+            })),
+            format,
+        };
+
+        // The bar shows the logged out state until the first poll
+        output.update(|_| {});
+
+        output
+    }
+
+    // Runs `f` over the tray state, as the tray handle does
+    pub fn update<R>(&self, f: impl FnOnce(&mut AppTray) -> R) -> R {
+        let mut state = self.state.lock().expect("bar state lock poisoned");
+
+        let result = f(&mut state.tray);
+        let line = render(self.format, &state.tray);
+
+        if line != state.last_line {
+            let mut stdout = std::io::stdout().lock();
+
+            // The bar closed the pipe, so there is nobody to show the usage to
+            if writeln!(stdout, "{}", line)
+                .and_then(|_| stdout.flush())
+                .is_err()
+            {
+                std::process::exit(0);
+            }
+
+            state.last_line = line;
+        }
+
+        result
+    }
+}
+
+// Line of the status bar showing the tray state
+fn render(format: BarFormat, tray: &AppTray) -> String {
+    let text = bar_text(tray);
+
+    match format {
+        BarFormat::Text => text,
+        BarFormat::Waybar => {
+            let tool_tip = ksni::Tray::tool_tip(tray);
+            let tooltip = if tool_tip.description.is_empty() {
+                tool_tip.title
+            } else {
+                format!("{}\n{}", tool_tip.title, tool_tip.description)
+            };
+
+            let line = WaybarLine {
+                text: escape_markup(&text),
+                tooltip: escape_markup(&tooltip),
+                class: bar_class(tray),
+                percentage: tray.worst_utilization().clamp(0.0, 100.0).round() as u32,
+            };
+
+            serde_json::to_string(&line).expect("bar line is serializable")
+        }
+    }
+}
+
+// Utilization of the current session and the week, the same shown by the icon rings
+fn bar_text(tray: &AppTray) -> String {
+    let language = tray.language();
+
+    let Some(usage) = tray.icon_usage() else {
+        return language.text(Text::NotLoggedIn).into();
+    };
+
+    if tray.accounts.iter().all(|account| account.usage.is_none()) {
+        return language.text(Text::LoadingUsage).into();
+    }
+
+    format!("5h {:.0}% · 7d {:.0}%", usage.five_hour, usage.seven_day)
+}
+
+// CSS class of the Waybar module, following the icon thresholds
+fn bar_class(tray: &AppTray) -> &'static str {
+    if tray.accounts.is_empty() {
+        return "logged-out";
+    }
+
+    if tray.is_rate_limited() {
+        return "rate-limited";
+    }
+
+    if tray.is_stale() {
+        return "offline";
+    }
+
+    let utilization = tray.worst_utilization();
+
+    if utilization >= tray.config.icon.critical {
+        "critical"
+    } else if utilization >= tray.config.icon.warning {
+        "warning"
+    } else {
+        "normal"
+    }
+}
+
+// Waybar renders the text as Pango markup
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrayAccount;
+    use crate::claude::tests::usage;
+    use crate::secret_service::tests::credentials;
+    use crate::tests::tray;
+    use crate::tracking::PollState;
+    use jiff::{SignedDuration, Timestamp};
+
+    // Account whose current session is at `utilization`
+    fn account(utilization: f32) -> TrayAccount {
+        let mut account = TrayAccount::new(credentials("first", 100));
+        account.usage = Some(usage(
+            utilization,
+            Timestamp::now() + SignedDuration::from_hours(2),
+        ));
+        account
+    }
+
+    fn waybar(tray: &AppTray) -> serde_json::Value {
+        serde_json::from_str(&render(BarFormat::Waybar, tray)).unwrap()
+    }
+
+    #[test]
+    fn text_line() {
+        assert_eq!(render(BarFormat::Text, &tray(Vec::new())), "not logged in");
+        assert_eq!(
+            render(
+                BarFormat::Text,
+                &tray(vec![TrayAccount::new(credentials("first", 100))])
+            ),
+            "Loading usage..."
+        );
+        assert_eq!(
+            render(BarFormat::Text, &tray(vec![account(62.4)])),
+            "5h 62% · 7d 0%"
+        );
+    }
+
+    #[test]
+    fn waybar_line() {
+        let line = waybar(&tray(vec![account(62.4)]));
+
+        assert_eq!(line["text"], "5h 62% · 7d 0%");
+        assert_eq!(line["class"], "warning");
+        assert_eq!(line["percentage"], 62);
+        assert!(
+            line["tooltip"]
+                .as_str()
+                .unwrap()
+                .contains("first@example.com")
+        );
+
+        let logged_out = waybar(&tray(Vec::new()));
+        assert_eq!(logged_out["class"], "logged-out");
+        assert_eq!(logged_out["percentage"], 0);
+    }
+
+    #[test]
+    fn waybar_class_follows_the_thresholds_and_the_poll_state() {
+        assert_eq!(waybar(&tray(vec![account(10.0)]))["class"], "normal");
+        assert_eq!(waybar(&tray(vec![account(80.0)]))["class"], "critical");
+
+        let mut offline = account(10.0);
+        offline.poll_state = PollState::Offline;
+        assert_eq!(waybar(&tray(vec![offline]))["class"], "offline");
+
+        let mut rate_limited = account(10.0);
+        rate_limited.poll_state = PollState::RateLimited {
+            until: Timestamp::now(),
+        };
+        assert_eq!(waybar(&tray(vec![rate_limited]))["class"], "rate-limited");
+    }
+
+    #[test]
+    fn escape_markup_of_waybar() {
+        assert_eq!(escape_markup("a & <b>"), "a &amp; &lt;b&gt;");
+    }
+}
@@ -1,13 +1,20 @@
 use base64::{Engine as _, engine::general_purpose};
-use log::{info, trace};
+use jiff::Timestamp;
+use log::{info, trace, warn};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha2::{Digest, Sha256};
-use std::fs;
-use std::io::{Read, Write};
-use std::net::TcpListener;
-use std::path::PathBuf;
-
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::claude_code;
+use crate::credentials::CredentialStore;
+use crate::error::ClaudeError;
+use crate::http;
 use crate::utils::extract_param_from_url;
 
 pub const CLAUDE_USAGE_URL: &str = "https://api.anthropic.com/api/oauth/usage";
@@ -20,50 +27,151 @@ pub const ANTHROPIC_CLIENT_ID: &str = "9d1c250a-e61b-44d9-88ed-5944d1962f5e";
 
 pub const ANTHROPIC_AUTH_SCOPE: &str = "user:profile user:inference user:sessions:claude_code";
 
-pub const OAUTH_REDIRECT_PORT: u16 = 54545;
+pub const DEFAULT_OAUTH_REDIRECT_PORT: u16 = 54545;
+
+pub const DEFAULT_CLAUDE_URL: &str = "https://claude.ai/new";
+
+// User agent of the requests. The usage endpoint expects the one of Claude Code
+pub const DEFAULT_USER_AGENT: &str = "claude-code/2.0.61";
+
+// How long before the access token expiration the refresh is performed.
+pub const TOKEN_REFRESH_MARGIN: Duration = Duration::from_mins(5);
+
+// How long the OAuth callback is waited for. The login is given up after it,
+// so the redirect port is released when the browser tab is closed
+const OAUTH_CALLBACK_TIMEOUT: Duration = Duration::from_mins(5);
+
+// Headers with the time a rate limit resets, checked after `Retry-After`. The
+// Anthropic ones have a RFC 3339 time and the others the seconds left
+const RATE_LIMIT_RESET_HEADERS: [&str; 4] = [
+    "anthropic-ratelimit-requests-reset",
+    "anthropic-ratelimit-unified-reset",
+    "ratelimit-reset",
+    "x-ratelimit-reset",
+];
+
+// Where the credentials in use were obtained from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CredentialSource {
+    // Login done by the tray and saved in its own credentials store
+    #[default]
+    Tray,
+    // Credentials shared with Claude Code (~/.claude/.credentials.json)
+    ClaudeCode,
+}
 
 // Wrapper for the OAuth credentials of Claude AI.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ClaudeCredentials {
     pub access_token: String,
     pub refresh_token: String,
+    // Unix timestamp (seconds) when the access token expires. Credentials
+    // saved by older versions don't have it.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    // Account that owns the credentials. Credentials saved by older
+    // versions don't have it.
+    #[serde(default)]
+    pub account: Option<AccountInfo>,
+    #[serde(skip)]
+    pub source: CredentialSource,
+}
+
+// Details of the account returned by the token endpoint,
+// used to label the account in the tray.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccountInfo {
+    pub uuid: String,
+    pub email_address: String,
+    pub organization_name: Option<String>,
+}
+
+impl ClaudeCredentials {
+    // Identifier of the account used to track it within the tray
+    // and the credentials store.
+    pub fn id(&self) -> String {
+        match (&self.account, self.source) {
+            (Some(account), _) => account.uuid.clone(),
+            (None, CredentialSource::ClaudeCode) => "claude-code".into(),
+            (None, CredentialSource::Tray) => "default".into(),
+        }
+    }
+
+    // Name of the account shown in the tray menu.
+    pub fn label(&self) -> String {
+        match (&self.account, self.source) {
+            (Some(account), _) => match &account.organization_name {
+                Some(organization) => format!("{} ({})", account.email_address, organization),
+                None => account.email_address.clone(),
+            },
+            (None, CredentialSource::ClaudeCode) => "Claude Code".into(),
+            (None, CredentialSource::Tray) => "Claude account".into(),
+        }
+    }
+
+    // Time left until the access token should be refreshed. It returns
+    // `None` when the expiration is unknown or the token is not refreshed
+    // by the tray (Claude Code refreshes its own credentials).
+    pub fn time_until_refresh(&self) -> Option<Duration> {
+        self.time_until_refresh_from(SystemTime::now())
+    }
+
+    fn time_until_refresh_from(&self, now: SystemTime) -> Option<Duration> {
+        if self.source == CredentialSource::ClaudeCode {
+            return None;
+        }
+
+        let expires_at = UNIX_EPOCH + Duration::from_secs(self.expires_at?);
+
+        Some(
+            expires_at
+                .checked_sub(TOKEN_REFRESH_MARGIN)
+                .and_then(|refresh_at| refresh_at.duration_since(now).ok())
+                .unwrap_or_default(),
+        )
+    }
 }
 
 // Error details structure for Claude API error responses
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ErrorDetails {
     pub error_visibility: String,
 }
 
 // Error structure for Claude API error responses
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ApiError {
     #[serde(rename = "type")]
     pub error_type: String,
     pub message: String,
-    pub details: ErrorDetails,
+    // Not every error has details
+    #[serde(default)]
+    pub details: Option<ErrorDetails>,
 }
 
 // Top-level error response from Claude API
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ClaudeErrorResponse {
     #[serde(rename = "type")]
     pub response_type: String, // "error"
     pub error: ApiError,
+    #[serde(default)]
     pub request_id: String,
 }
 
 // It represents the usage period of an account in detail.
 // This struct is used inside the response of the Claude API
 // usage endpoint.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UsagePeriod {
     pub utilization: f32,
-    pub resets_at: Option<String>,
+    #[serde(default)]
+    pub resets_at: Option<Timestamp>,
 }
 
 // It is part of the response of the Claude API usage endpoint.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct ExtraUsage {
     pub is_enabled: bool,
     pub monthly_limit: Option<u64>,
@@ -71,19 +179,215 @@ pub struct ExtraUsage {
     pub utilization: Option<f32>,
 }
 
-// It is the full response of the Claude API usage endpoint.
-#[derive(Debug, Deserialize, Serialize)]
+// It is the full response of the Claude API usage endpoint. Every section is
+// optional, so a change in the response doesn't prevent reading the rest of it.
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ClaudeUsageResponse {
     // Information about the usage of the account (Current session on the tray).
-    pub five_hour: UsagePeriod,
+    pub five_hour: Option<UsagePeriod>,
     // Information about the usage of the account (All models).
-    pub seven_day: UsagePeriod,
+    pub seven_day: Option<UsagePeriod>,
     pub seven_day_oauth_apps: Option<UsagePeriod>,
     pub seven_day_opus: Option<UsagePeriod>,
     pub seven_day_sonnet: Option<UsagePeriod>,
     pub iguana_necktie: Option<UsagePeriod>,
     pub seven_day_iguana_necktie: Option<UsagePeriod>,
     pub extra_usage: ExtraUsage,
+    // Usage periods unknown by this version, by their field name
+    #[serde(flatten)]
+    pub other_periods: BTreeMap<String, UsagePeriod>,
+}
+
+// Field names of the usage periods known by this version, in
+// the order they are declared in `ClaudeUsageResponse`.
+pub const KNOWN_PERIODS: [&str; 7] = [
+    "five_hour",
+    "seven_day",
+    "seven_day_oauth_apps",
+    "seven_day_opus",
+    "seven_day_sonnet",
+    "iguana_necktie",
+    "seven_day_iguana_necktie",
+];
+
+// Window of time a usage period is measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageWindow {
+    FiveHour,
+    SevenDay,
+    // Periods with a window unknown by this version
+    Other,
+}
+
+impl UsageWindow {
+    // Window of a usage period from its field name. Fields that don't name
+    // their window (e.g. "iguana_necktie") are not assumed to be in one.
+    fn from_key(key: &str) -> Self {
+        if key.starts_with("five_hour") {
+            Self::FiveHour
+        } else if key.starts_with("seven_day") {
+            Self::SevenDay
+        } else {
+            Self::Other
+        }
+    }
+}
+
+// Usage period present in the usage response, with a readable name.
+#[derive(Debug, Clone)]
+pub struct UsageBucket<'a> {
+    // Name of the field in the usage response
+    pub key: String,
+    pub label: String,
+    pub window: UsageWindow,
+    pub period: &'a UsagePeriod,
+}
+
+// Readable name of a usage period unknown by this version, built from its
+// field name without the window (e.g. "seven_day_haiku" -> "Haiku").
+fn period_label(key: &str) -> String {
+    let name = ["five_hour_", "seven_day_"]
+        .iter()
+        .find_map(|prefix| key.strip_prefix(prefix))
+        .unwrap_or(key)
+        .replace('_', " ");
+
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+// Usage period fields are named after their window (e.g. "five_hour", "seven_day_opus").
+fn is_period_key(key: &str) -> bool {
+    key.contains("_hour") || key.contains("_day") || key == "iguana_necktie"
+}
+
+impl ClaudeUsageResponse {
+    // Parses the response of the usage endpoint. Every field is parsed on its own:
+    // unknown usage periods are kept in `other_periods` and the fields that cannot
+    // be parsed are skipped. The differences with the expected schema are
+    // reported as warnings.
+    pub fn parse(response: &str) -> Result<Self, ClaudeError> {
+        let fields: serde_json::Map<String, serde_json::Value> = serde_json::from_str(response)
+            .map_err(|e| ClaudeError::Parse(format!("unexpected api response format: {}", e)))?;
+
+        let mut usage = Self::default();
+
+        for (key, value) in fields {
+            if value.is_null() {
+                continue;
+            }
+
+            if key == "extra_usage" {
+                match serde_json::from_value(value) {
+                    Ok(extra_usage) => usage.extra_usage = extra_usage,
+                    Err(e) => warn!("usage schema drift: invalid extra_usage: {}", e),
+                }
+            } else if is_period_key(&key) {
+                match serde_json::from_value(value) {
+                    Ok(period) => usage.set_period(key, period),
+                    Err(e) => warn!("usage schema drift: invalid usage period {}: {}", key, e),
+                }
+            } else {
+                warn!("usage schema drift: unknown field {}", key);
+            }
+        }
+
+        for (key, period) in [
+            ("five_hour", &usage.five_hour),
+            ("seven_day", &usage.seven_day),
+        ] {
+            if period.is_none() {
+                warn!("usage schema drift: missing usage period {}", key);
+            }
+        }
+
+        Ok(usage)
+    }
+
+    fn set_period(&mut self, key: String, period: UsagePeriod) {
+        let field = match key.as_str() {
+            "five_hour" => &mut self.five_hour,
+            "seven_day" => &mut self.seven_day,
+            "seven_day_oauth_apps" => &mut self.seven_day_oauth_apps,
+            "seven_day_opus" => &mut self.seven_day_opus,
+            "seven_day_sonnet" => &mut self.seven_day_sonnet,
+            "iguana_necktie" => &mut self.iguana_necktie,
+            "seven_day_iguana_necktie" => &mut self.seven_day_iguana_necktie,
+            _ => {
+                info!("new usage period found: {}", key);
+                self.other_periods.insert(key, period);
+                return;
+            }
+        };
+
+        *field = Some(period);
+    }
+
+    // Every usage period returned by the API. The absent ones are skipped.
+    pub fn buckets(&self) -> Vec<UsageBucket<'_>> {
+        let periods = [
+            ("Current session", "five_hour", self.five_hour.as_ref()),
+            (
+                "Iguana necktie",
+                "iguana_necktie",
+                self.iguana_necktie.as_ref(),
+            ),
+            ("All models", "seven_day", self.seven_day.as_ref()),
+            ("Opus", "seven_day_opus", self.seven_day_opus.as_ref()),
+            ("Sonnet", "seven_day_sonnet", self.seven_day_sonnet.as_ref()),
+            (
+                "OAuth apps",
+                "seven_day_oauth_apps",
+                self.seven_day_oauth_apps.as_ref(),
+            ),
+            (
+                "Iguana necktie",
+                "seven_day_iguana_necktie",
+                self.seven_day_iguana_necktie.as_ref(),
+            ),
+        ];
+
+        let known = periods.into_iter().filter_map(|(label, key, period)| {
+            Some(UsageBucket {
+                key: key.into(),
+                label: label.into(),
+                window: UsageWindow::from_key(key),
+                period: period?,
+            })
+        });
+
+        let other = self.other_periods.iter().map(|(key, period)| UsageBucket {
+            key: key.clone(),
+            label: period_label(key),
+            window: UsageWindow::from_key(key),
+            period,
+        });
+
+        known.chain(other).collect()
+    }
+
+    // Highest utilization between the periods of a window
+    pub fn utilization(&self, window: UsageWindow) -> f32 {
+        self.buckets()
+            .iter()
+            .filter(|bucket| bucket.window == window)
+            .map(|bucket| bucket.period.utilization)
+            .fold(0.0, f32::max)
+    }
+
+    // Closest reset time in the future between the usage periods
+    pub fn next_reset(&self) -> Option<Timestamp> {
+        let now = Timestamp::now();
+
+        self.buckets()
+            .iter()
+            .filter_map(|bucket| bucket.period.resets_at)
+            .filter(|resets_at| *resets_at > now)
+            .min()
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -108,6 +412,23 @@ pub struct AnthropicTokenResponse {
     pub account: Account,
 }
 
+// Response of the token endpoint when using the refresh_token grant. The
+// refresh token is only present when the server rotates it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AnthropicRefreshResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: u64,
+}
+
+// Converts the `expires_in` seconds of a token response into an absolute timestamp.
+fn expires_at_from(expires_in: u64) -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|now| now.as_secs() + expires_in)
+}
+
 // Generates a code verifier for OAuth2 authorization.
 pub fn generate_code_verifier() -> String {
     let random_bytes: [u8; 32] = rand::random();
@@ -129,52 +450,162 @@ pub fn generate_code_challenge(code_verifier: &str) -> String {
     general_purpose::URL_SAFE_NO_PAD.encode(hash)
 }
 
-// Runs a localhost server to wait for the OAuth callback.
-pub async fn wait_for_oauth_callback(expected_state: &str) -> Result<String, String> {
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", OAUTH_REDIRECT_PORT))
-        .map_err(|e| format!("failed to bind to port {}: {}", OAUTH_REDIRECT_PORT, e))?;
-
-    trace!("oauth callback listening on port {}", OAUTH_REDIRECT_PORT);
+// Runs a localhost server to wait for the OAuth callback. It fails when
+// no callback is received in `OAUTH_CALLBACK_TIMEOUT`.
+pub async fn wait_for_oauth_callback(
+    expected_state: &str,
+    redirect_port: u16,
+) -> Result<String, ClaudeError> {
+    // The port is taken by another program, so another one has to be configured
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", redirect_port))
+        .await
+        .map_err(|e| {
+            ClaudeError::Config(format!(
+                "failed to bind to port {}: {}. set another oauth_redirect_port",
+                redirect_port, e
+            ))
+        })?;
+
+    trace!("oauth callback listening on port {}", redirect_port);
+
+    tokio::time::timeout(
+        OAUTH_CALLBACK_TIMEOUT,
+        receive_oauth_callback(&listener, expected_state),
+    )
+    .await
+    .map_err(|_| {
+        ClaudeError::Timeout(format!(
+            "no authorization received in {} minutes",
+            OAUTH_CALLBACK_TIMEOUT.as_secs() / 60
+        ))
+    })?
+}
 
+async fn receive_oauth_callback(
+    listener: &TcpListener,
+    expected_state: &str,
+) -> Result<String, ClaudeError> {
     // Waiting for a connection
     let (mut stream, _) = listener
         .accept()
-        .map_err(|e| format!("failed to accept connection: {}", e))?;
+        .await
+        .map_err(|e| ClaudeError::Io(format!("failed to accept connection: {}", e)))?;
 
     // Reading a HTTP request
     let mut buffer = [0; 1024];
     stream
         .read(&mut buffer)
-        .map_err(|e| format!("failed to read from stream: {}", e))?;
+        .await
+        .map_err(|e| ClaudeError::Io(format!("failed to read from stream: {}", e)))?;
 
     let request = String::from_utf8_lossy(&buffer);
 
-    let received_state = extract_param_from_url(&request, "state")?;
+    let received_state = extract_param_from_url(&request, "state").map_err(ClaudeError::Parse)?;
 
     if received_state != expected_state {
-        return Err("state value is not the same".into());
+        return Err(ClaudeError::StateMismatch);
     }
 
-    let code = extract_param_from_url(&request, "code")?;
+    let code = extract_param_from_url(&request, "code").map_err(ClaudeError::Parse)?;
 
     let response = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html><body><h1>Success</h1></body></html>";
 
     stream
         .write_all(response.as_bytes())
-        .map_err(|e| format!("failed to write to stream: {}", e))?;
+        .await
+        .map_err(|e| ClaudeError::Io(format!("failed to write to stream: {}", e)))?;
 
     Ok(code)
 }
 
+// Classifies an unsuccessful response of the API or the OAuth server.
+fn response_error(status: StatusCode, headers: &HeaderMap, body: &str) -> ClaudeError {
+    // An overloaded server asks to back off the same way a rate limit does
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+        return ClaudeError::RateLimited {
+            status,
+            retry_after: retry_after(headers, Timestamp::now()),
+        };
+    }
+
+    let api_error = serde_json::from_str::<ClaudeErrorResponse>(body).ok();
+
+    // The token endpoint answers with OAuth errors, e.g. {"error": "invalid_grant"}
+    // when the refresh token was revoked or already used
+    let is_invalid_grant = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .is_some_and(|value| value["error"] == "invalid_grant");
+
+    if status == StatusCode::UNAUTHORIZED || is_invalid_grant {
+        return ClaudeError::Unauthorized(match api_error {
+            Some(response) => response.error.message,
+            None => format!("request rejected with status {}", status),
+        });
+    }
+
+    match api_error {
+        Some(response) => ClaudeError::Api { status, response },
+        None => ClaudeError::Http {
+            status,
+            body: body.into(),
+        },
+    }
+}
+
+// Time to wait before the next request, from the `Retry-After` header (seconds or
+// an HTTP date) or the rate limit headers. The reset time can be a RFC 3339 time,
+// the seconds left or, in some servers, a Unix time.
+fn retry_after(headers: &HeaderMap, now: Timestamp) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    let until = |time: Timestamp| Duration::try_from(time.duration_since(now)).ok();
+
+    if let Some(value) = header(RETRY_AFTER.as_str()) {
+        let delay = match value.parse::<u64>() {
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            Err(_) => jiff::fmt::rfc2822::DateTimeParser::new()
+                .parse_timestamp(value)
+                .ok()
+                .and_then(until),
+        };
+
+        if delay.is_some() {
+            return delay;
+        }
+    }
+
+    RATE_LIMIT_RESET_HEADERS.iter().find_map(|name| {
+        let value = header(name)?;
+
+        if let Ok(time) = value.parse::<Timestamp>() {
+            return until(time);
+        }
+
+        let secs = value.parse::<f64>().ok().filter(|secs| *secs >= 0.0)?;
+
+        // Values past a billion seconds are Unix times, not seconds left
+        if secs >= 1e9 {
+            until(Timestamp::from_second(secs as i64).ok()?)
+        } else {
+            Duration::try_from_secs_f64(secs).ok()
+        }
+    })
+}
+
 // Function to exchange code received from the OAuth server for an access token
 async fn exchange_code_for_token(
     code: &str,
     state: &str,
     code_verifier: &str,
-) -> Result<AnthropicTokenResponse, String> {
-    let client = reqwest::Client::new();
+    redirect_port: u16,
+) -> Result<AnthropicTokenResponse, ClaudeError> {
+    let client = http::client();
 
-    let redirect_url = format!("http://localhost:{}/callback", OAUTH_REDIRECT_PORT);
+    let redirect_url = format!("http://localhost:{}/callback", redirect_port);
 
     let request_body = json!({
         "code": code,
@@ -185,8 +616,6 @@ async fn exchange_code_for_token(
         "code_verifier": code_verifier
     });
 
-    trace!("token exchange request body: {}", request_body);
-
     let response = client
         .post(ANTHROPIC_TOKEN_URL)
         .header("Content-Type", "application/json")
@@ -194,171 +623,714 @@ async fn exchange_code_for_token(
         .json(&request_body)
         .send()
         .await
-        .map_err(|e| format!("failed to send request: {}", e))?;
+        .map_err(|e| ClaudeError::Network(format!("failed to send request: {}", e)))?;
 
     let status = response.status();
+    let headers = response.headers().clone();
 
     let response_text = response
         .text()
         .await
-        .map_err(|e| format!("failed to read response: {}", e))?;
+        .map_err(|e| ClaudeError::Network(format!("failed to read response: {}", e)))?;
 
-    trace!(
-        "token exchange response (status {}): {}",
-        status, response_text
-    );
+    // The body holds the tokens, so it is never logged
+    trace!("token exchange response (status {})", status);
 
     if !status.is_success() {
-        return Err(format!(
-            "token exchange failed with status {}: {}",
-            status, response_text
-        ));
+        return Err(response_error(status, &headers, &response_text));
     }
 
-    serde_json::from_str::<AnthropicTokenResponse>(&response_text)
-        .map_err(|e| format!("failed to parse token response: {}", e))
+    let token = serde_json::from_str::<AnthropicTokenResponse>(&response_text)
+        .map_err(|e| ClaudeError::Parse(format!("failed to parse token response: {}", e)))?;
+
+    trace!("access token expires in {} seconds", token.expires_in);
+
+    Ok(token)
 }
 
-// Function to login to Claude API. It opens a terminal executing `claude /login`.
-// When the user exits claude code execution, the terminal is closed and the
-// function tries to get the credentials.
-pub async fn open_oauth_login() -> Result<AnthropicTokenResponse, String> {
-    info!("starting oauth login flow");
+// Function to exchange the refresh token for a new access token
+async fn exchange_refresh_token(
+    refresh_token: &str,
+) -> Result<AnthropicRefreshResponse, ClaudeError> {
+    let client = http::client();
 
-    let state = generate_state();
-    let code_verifier = generate_code_verifier();
+    let request_body = json!({
+        "grant_type": "refresh_token",
+        "refresh_token": refresh_token,
+        "client_id": ANTHROPIC_CLIENT_ID,
+    });
 
-    let code_challenge = generate_code_challenge(&code_verifier);
+    let response = client
+        .post(ANTHROPIC_TOKEN_URL)
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .json(&request_body)
+        .send()
+        .await
+        .map_err(|e| ClaudeError::Network(format!("failed to send request: {}", e)))?;
 
-    trace!("generated pkce verifier and challenge");
+    let status = response.status();
+    let headers = response.headers().clone();
 
-    let redirect_url = format!("http://localhost:{}/callback", OAUTH_REDIRECT_PORT);
-    let auth_url = format!(
-        "{}?code=true&client_id={}&response_type=code&redirect_uri={}&scope={}&code_challenge={}&code_challenge_method=S256&state={}",
-        ANTHROPIC_AUTH_URL,                        // Url
-        ANTHROPIC_CLIENT_ID,                       // Claude client ID
-        urlencoding::encode(&redirect_url),        // Redirect URL
-        urlencoding::encode(ANTHROPIC_AUTH_SCOPE), // Scope
-        code_challenge,                            // Code challenge
-        state                                      // State
+    let response_text = response
+        .text()
+        .await
+        .map_err(|e| ClaudeError::Network(format!("failed to read response: {}", e)))?;
+
+    // The body holds the tokens, so it is never logged
+    trace!("token refresh response (status {})", status);
+
+    if !status.is_success() {
+        return Err(response_error(status, &headers, &response_text));
+    }
+
+    let refreshed = serde_json::from_str::<AnthropicRefreshResponse>(&response_text)
+        .map_err(|e| ClaudeError::Parse(format!("failed to parse refresh response: {}", e)))?;
+
+    trace!(
+        "refreshed access token expires in {} seconds",
+        refreshed.expires_in
     );
 
-    info!("opening browser for authorization");
-    webbrowser::open(&auth_url).map_err(|e| format!("failed to open browser: {}", e))?;
+    Ok(refreshed)
+}
+
+// Stored credentials of an account, when another process (e.g. `claude-tray
+// status` next to the tray) refreshed its token already. The refresh token
+// kept in memory was rotated then, so it must not be used again.
+fn refreshed_elsewhere(
+    credentials: &ClaudeCredentials,
+    stored: Option<ClaudeCredentials>,
+    now: SystemTime,
+) -> Option<ClaudeCredentials> {
+    stored
+        .filter(|stored| stored.access_token != credentials.access_token)
+        .filter(|stored| stored.time_until_refresh_from(now) != Some(Duration::ZERO))
+}
+
+// Credentials with the tokens of a refresh response. The current refresh
+// token is kept when the server didn't rotate it.
+fn with_refreshed_token(
+    credentials: &ClaudeCredentials,
+    refreshed: AnthropicRefreshResponse,
+) -> ClaudeCredentials {
+    ClaudeCredentials {
+        access_token: refreshed.access_token,
+        refresh_token: refreshed
+            .refresh_token
+            .unwrap_or_else(|| credentials.refresh_token.clone()),
+        expires_at: expires_at_from(refreshed.expires_in),
+        account: credentials.account.clone(),
+        source: CredentialSource::Tray,
+    }
+}
+
+// Obtains a new access token using the stored refresh token and
+// persists the new credentials.
+pub async fn refresh_credentials(
+    credentials: &ClaudeCredentials,
+) -> Result<ClaudeCredentials, ClaudeError> {
+    // Refreshing the token of Claude Code would rotate the refresh token it
+    // keeps, logging it out. Its file is read again instead, expecting
+    // Claude Code to have refreshed it.
+    if credentials.source == CredentialSource::ClaudeCode {
+        return claude_code::load_credentials()?
+            .filter(|reloaded| reloaded.access_token != credentials.access_token)
+            .ok_or(ClaudeError::Unauthorized(
+                "claude code credentials expired. waiting for claude code to refresh them".into(),
+            ));
+    }
+
+    let stored = read_stored_credentials(&credentials.id()).await;
+
+    if let Some(stored) = refreshed_elsewhere(credentials, stored, SystemTime::now()) {
+        info!("access token already refreshed by another process");
+        return Ok(stored);
+    }
+
+    info!("refreshing access token");
+
+    let refreshed = exchange_refresh_token(&credentials.refresh_token).await?;
+    let credentials = with_refreshed_token(credentials, refreshed);
+
+    write_credentials(&credentials).await?;
 
-    info!("waiting for oauth callback");
-    let auth_code = wait_for_oauth_callback(&state).await?;
-    info!("received authorization code");
+    info!("access token refreshed successfully");
 
-    info!("exchanging authorization code for tokens");
-    let token_exchanged = exchange_code_for_token(&auth_code, &state, &code_verifier).await?;
-    info!("successfully obtained access token");
+    Ok(credentials)
+}
+
+// Authorization request of the OAuth login flow, waiting for the user
+// to open its url and authorize the tray.
+#[derive(Debug, Clone)]
+pub struct OAuthAuthorization {
+    pub auth_url: String,
+    state: String,
+    code_verifier: String,
+    redirect_port: u16,
+}
+
+impl OAuthAuthorization {
+    // Builds the authorization url with a new PKCE challenge. The OAuth
+    // callback is received on `redirect_port`.
+    pub fn new(redirect_port: u16) -> Self {
+        let state = generate_state();
+        let code_verifier = generate_code_verifier();
+
+        let code_challenge = generate_code_challenge(&code_verifier);
+
+        trace!("generated pkce verifier and challenge");
+
+        let redirect_url = format!("http://localhost:{}/callback", redirect_port);
+        let auth_url = format!(
+            "{}?code=true&client_id={}&response_type=code&redirect_uri={}&scope={}&code_challenge={}&code_challenge_method=S256&state={}",
+            ANTHROPIC_AUTH_URL,                        // Url
+            ANTHROPIC_CLIENT_ID,                       // Claude client ID
+            urlencoding::encode(&redirect_url),        // Redirect URL
+            urlencoding::encode(ANTHROPIC_AUTH_SCOPE), // Scope
+            code_challenge,                            // Code challenge
+            state                                      // State
+        );
+
+        Self {
+            auth_url,
+            state,
+            code_verifier,
+            redirect_port,
+        }
+    }
+
+    // Waits for the OAuth callback and exchanges the received code for tokens.
+    pub async fn finish(&self) -> Result<AnthropicTokenResponse, ClaudeError> {
+        info!("waiting for oauth callback");
+        let auth_code = wait_for_oauth_callback(&self.state, self.redirect_port).await?;
+        info!("received authorization code");
+
+        info!("exchanging authorization code for tokens");
+        let token_exchanged = exchange_code_for_token(
+            &auth_code,
+            &self.state,
+            &self.code_verifier,
+            self.redirect_port,
+        )
+        .await?;
+        info!("successfully obtained access token");
+
+        Ok(token_exchanged)
+    }
+}
+
+// Function to login to Claude API. It opens the authorization url in the
+// browser and waits until the user authorizes the tray. The OAuth callback
+// is received on `redirect_port`.
+pub async fn open_oauth_login(redirect_port: u16) -> Result<AnthropicTokenResponse, ClaudeError> {
+    info!("starting oauth login flow");
+
+    let authorization = OAuthAuthorization::new(redirect_port);
+
+    info!("opening browser for authorization");
+    webbrowser::open(&authorization.auth_url)
+        .map_err(|e| ClaudeError::Io(format!("failed to open browser: {}", e)))?;
 
-    Ok(token_exchanged)
+    authorization.finish().await
 }
 
 // Function to get the usage of the account. It receives the access token and returns the usage response.
-pub async fn get_usage(access_token: &str) -> Result<ClaudeUsageResponse, String> {
+pub async fn get_usage(access_token: &str) -> Result<ClaudeUsageResponse, ClaudeError> {
     info!("getting usage user information from {}", CLAUDE_USAGE_URL);
 
-    let response = reqwest::Client::new()
+    let response = http::client()
         .get(CLAUDE_USAGE_URL)
         .header(
             reqwest::header::AUTHORIZATION,
             format!("Bearer {}", access_token),
         )
         .header("anthropic-beta", "oauth-2025-04-20")
-        .header(reqwest::header::USER_AGENT, "claude-code/2.0.61")
         .header(reqwest::header::ACCEPT, "application/json")
         .send()
         .await
-        .map_err(|e| format!("error requesting usage: {}", e))?;
+        .map_err(|e| ClaudeError::Network(format!("error requesting usage: {}", e)))?;
 
     let status = response.status();
+    let headers = response.headers().clone();
     let response_text = response
         .text()
         .await
-        .map_err(|e| format!("error reading response text: {}", e))?;
+        .map_err(|e| ClaudeError::Network(format!("error reading response text: {}", e)))?;
 
     info!("request response (status {}): {}", status, response_text);
 
-    // Try to parse as success response first
-    if let Ok(usage) = serde_json::from_str::<ClaudeUsageResponse>(&response_text) {
-        return Ok(usage);
+    if status.is_success() {
+        return ClaudeUsageResponse::parse(&response_text);
     }
 
-    if let Ok(error_response) = serde_json::from_str::<ClaudeErrorResponse>(&response_text) {
-        return Err(format!(
-            "api error ({}): {} [request_id: {}]",
-            error_response.error.error_type,
-            error_response.error.message,
-            error_response.request_id
-        ));
-    }
-
-    Err(format!("unexpected api response format: {}", response_text))
+    Err(response_error(status, &headers, &response_text))
 }
 
-// Function to get the credentials of the account. By default, the
-// credentials are stored in a json file within the $HOME/.claude directory.
-pub fn get_local_credentials() -> Result<ClaudeCredentials, String> {
-    trace!("getting $HOME environment variable");
+// Function to get the credentials of every account. The credentials of Claude Code
+// ($HOME/.claude/.credentials.json) are used when it is logged in, besides the ones
+// of the tray, stored in the user keyring or in $HOME/.config/claude-tray/credentials.json
+// when no Secret Service provider is available.
+pub async fn get_local_credentials() -> Result<Vec<ClaudeCredentials>, ClaudeError> {
+    let store = CredentialStore::shared().await?;
 
-    let env_home =
-        std::env::var("HOME").map_err(|e| format!("home environment variable not set: {}", e))?;
+    trace!("reading credentials from the {}", store.name());
 
-    trace!(
-        "reading credentials file located in {}/.config/claude-tray/credentials.json",
-        env_home
-    );
+    let mut accounts = store.load().await?;
 
-    let credentials =
-        fs::read_to_string(format!("{}/.config/claude-tray/credentials.json", env_home))
-            .map_err(|e| format!("failed to read credentials file: {}", e))?;
+    info!("{} accounts found in the {}", accounts.len(), store.name());
 
-    let credentials: ClaudeCredentials = serde_json::from_str(&credentials)
-        .map_err(|e| format!("error getting credentials: {}", e))?;
+    match claude_code::load_credentials() {
+        // The account is skipped if it was also logged in the tray
+        Ok(Some(credentials)) if !accounts.iter().any(|a| a.id() == credentials.id()) => {
+            accounts.insert(0, credentials);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("{}", e),
+    }
 
-    info!(
-        "credentials found in {}/.config/claude-tray/credentials.json",
-        env_home
-    );
+    if accounts.is_empty() {
+        return Err(ClaudeError::Unauthorized("no credentials found".into()));
+    }
 
-    Ok(credentials)
+    Ok(accounts)
 }
 
-// Store the credentials in the file credentials.json
-pub fn save_credentials_locally(
+// Store the credentials of a new login in the configured credentials
+// store, replacing the previous ones of the same account.
+pub async fn save_credentials_locally(
     credentials: &AnthropicTokenResponse,
-) -> Result<ClaudeCredentials, String> {
-    let env_home =
-        std::env::var("HOME").map_err(|e| format!("home environment variable not set: {}", e))?;
+) -> Result<ClaudeCredentials, ClaudeError> {
+    let credentials_json = ClaudeCredentials {
+        access_token: credentials.access_token.clone(),
+        refresh_token: credentials.refresh_token.clone(),
+        expires_at: expires_at_from(credentials.expires_in),
+        account: Some(AccountInfo {
+            uuid: credentials.account.uuid.clone(),
+            email_address: credentials.account.email_address.clone(),
+            organization_name: Some(credentials.organization.name.clone()),
+        }),
+        source: CredentialSource::Tray,
+    };
 
-    let config_dir = PathBuf::from(env_home).join(".config/claude-tray");
+    write_credentials(&credentials_json).await?;
 
-    trace!("saving credentials to {:?}", config_dir);
+    Ok(credentials_json)
+}
 
-    if !config_dir.exists() {
-        info!("credentials file not exists. creating new file");
+// Removes the credentials of an account saved by the tray. The
+// credentials of Claude Code are left untouched.
+pub async fn delete_local_credentials(account_id: &str) -> Result<(), ClaudeError> {
+    let store = CredentialStore::shared().await?;
 
-        fs::create_dir_all(&config_dir)
-            .map_err(|e| format!("failed to create config directory: {}", e))?;
+    let mut accounts = store.load().await?;
+    accounts.retain(|account| account.id() != account_id);
+
+    if accounts.is_empty() {
+        store.delete().await?;
+    } else {
+        store.save(&accounts).await?;
     }
 
-    let credentials_json = ClaudeCredentials {
-        access_token: credentials.access_token.clone(),
-        refresh_token: credentials.refresh_token.clone(),
+    info!("credentials removed from the {}", store.name());
+
+    Ok(())
+}
+
+// Reads the stored credentials of an account, if any. Errors are
+// only logged, as the credentials in memory can still be used.
+async fn read_stored_credentials(account_id: &str) -> Option<ClaudeCredentials> {
+    let accounts = match CredentialStore::shared().await {
+        Ok(store) => store.load().await,
+        Err(e) => Err(e),
     };
 
-    let json_fmt = serde_json::to_string_pretty(&credentials_json)
-        .map_err(|e| format!("failed to serialize credentials: {}", e))?;
+    accounts
+        .map_err(|e| warn!("{}", e))
+        .ok()?
+        .into_iter()
+        .find(|account| account.id() == account_id)
+}
 
-    let credentials_file = config_dir.join("credentials.json");
+async fn write_credentials(credentials: &ClaudeCredentials) -> Result<(), ClaudeError> {
+    let store = CredentialStore::shared().await?;
 
-    fs::write(&credentials_file, json_fmt)
-        .map_err(|e| format!("failed to write credentials file: {}", e))?;
+    let mut accounts = store.load().await?;
 
-    info!("credentials saved successfully");
+    match accounts.iter_mut().find(|a| a.id() == credentials.id()) {
+        Some(account) => *account = credentials.clone(),
+        None => accounts.push(credentials.clone()),
+    }
 
-    Ok(credentials_json)
+    store.save(&accounts).await?;
+
+    info!("credentials saved successfully in the {}", store.name());
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::secret_service::tests::credentials;
+    use reqwest::header::HeaderValue;
+
+    // Five hour usage resetting at `resets_at`
+    pub fn usage(utilization: f32, resets_at: Timestamp) -> ClaudeUsageResponse {
+        let response = serde_json::json!({
+            "five_hour": { "utilization": utilization, "resets_at": resets_at.to_string() },
+        });
+
+        ClaudeUsageResponse::parse(&response.to_string()).unwrap()
+    }
+
+    fn parse_fixture(name: &str) -> ClaudeUsageResponse {
+        let path = format!(
+            "{}/tests/fixtures/usage/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let response = std::fs::read_to_string(&path).unwrap();
+
+        ClaudeUsageResponse::parse(&response).unwrap()
+    }
+
+    fn bucket_keys(usage: &ClaudeUsageResponse) -> Vec<String> {
+        usage
+            .buckets()
+            .into_iter()
+            .map(|bucket| bucket.key)
+            .collect()
+    }
+
+    #[test]
+    fn parse_current_response() {
+        let usage = parse_fixture("current");
+
+        assert_eq!(usage.five_hour.as_ref().unwrap().utilization, 23.0);
+        assert_eq!(
+            usage.five_hour.as_ref().unwrap().resets_at,
+            Some("2026-10-16T14:00:00.123456Z".parse().unwrap())
+        );
+        assert_eq!(usage.seven_day.as_ref().unwrap().utilization, 41.0);
+        assert_eq!(usage.seven_day_opus.as_ref().unwrap().resets_at, None);
+        assert!(usage.seven_day_sonnet.is_none());
+        assert!(usage.other_periods.is_empty());
+
+        assert!(usage.extra_usage.is_enabled);
+        assert_eq!(usage.extra_usage.monthly_limit, Some(5000));
+        assert_eq!(usage.extra_usage.used_credits, Some(1250));
+        assert_eq!(usage.extra_usage.utilization, Some(25.0));
+
+        assert_eq!(
+            bucket_keys(&usage),
+            ["five_hour", "seven_day", "seven_day_opus"]
+        );
+    }
+
+    #[test]
+    fn parse_old_response_without_extra_usage() {
+        let usage = parse_fixture("old");
+
+        assert_eq!(usage.five_hour.as_ref().unwrap().utilization, 7.0);
+        assert_eq!(usage.seven_day.as_ref().unwrap().utilization, 3.0);
+        assert!(usage.seven_day_oauth_apps.is_none());
+        assert!(usage.seven_day_opus.is_none());
+        assert!(!usage.extra_usage.is_enabled);
+        assert_eq!(usage.extra_usage.monthly_limit, None);
+        assert_eq!(bucket_keys(&usage), ["five_hour", "seven_day"]);
+    }
+
+    #[test]
+    fn parse_future_response_with_unknown_periods() {
+        let usage = parse_fixture("future");
+
+        assert_eq!(
+            usage.other_periods.keys().collect::<Vec<_>>(),
+            ["seven_day_haiku", "two_hour"]
+        );
+
+        let buckets = usage.buckets();
+        let haiku = buckets
+            .iter()
+            .find(|bucket| bucket.key == "seven_day_haiku")
+            .unwrap();
+        assert_eq!(haiku.label, "Haiku");
+        assert_eq!(haiku.window, UsageWindow::SevenDay);
+        assert_eq!(haiku.period.utilization, 15.0);
+
+        let two_hour = buckets
+            .iter()
+            .find(|bucket| bucket.key == "two_hour")
+            .unwrap();
+        assert_eq!(two_hour.window, UsageWindow::Other);
+
+        // Unknown periods of a known window count for its utilization
+        assert_eq!(usage.utilization(UsageWindow::SevenDay), 60.0);
+        assert_eq!(usage.utilization(UsageWindow::Other), 80.0);
+    }
+
+    #[test]
+    fn window_of_periods_without_a_known_window() {
+        assert_eq!(UsageWindow::from_key("five_hour"), UsageWindow::FiveHour);
+        assert_eq!(
+            UsageWindow::from_key("seven_day_iguana_necktie"),
+            UsageWindow::SevenDay
+        );
+        assert_eq!(UsageWindow::from_key("iguana_necktie"), UsageWindow::Other);
+    }
+
+    #[test]
+    fn parse_skips_invalid_fields() {
+        let usage = parse_fixture("invalid_field");
+
+        assert!(usage.five_hour.is_none());
+        assert!(usage.seven_day_opus.is_none());
+        assert_eq!(usage.seven_day.as_ref().unwrap().utilization, 60.0);
+        assert!(!usage.extra_usage.is_enabled);
+        assert_eq!(bucket_keys(&usage), ["seven_day"]);
+    }
+
+    #[test]
+    fn parse_rejects_non_object_response() {
+        assert!(matches!(
+            ClaudeUsageResponse::parse("[]"),
+            Err(ClaudeError::Parse(_))
+        ));
+        assert!(matches!(
+            ClaudeUsageResponse::parse("<html>"),
+            Err(ClaudeError::Parse(_))
+        ));
+    }
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn now() -> Timestamp {
+        "2026-10-16T10:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(
+            retry_after(&headers("retry-after", "120"), now()),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        assert_eq!(
+            retry_after(
+                &headers("retry-after", "Fri, 16 Oct 2026 10:01:30 GMT"),
+                now()
+            ),
+            Some(Duration::from_secs(90))
+        );
+    }
+
+    #[test]
+    fn retry_after_past_http_date() {
+        assert_eq!(
+            retry_after(
+                &headers("retry-after", "Fri, 16 Oct 2026 09:59:00 GMT"),
+                now()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn retry_after_rfc3339_reset() {
+        assert_eq!(
+            retry_after(
+                &headers("anthropic-ratelimit-requests-reset", "2026-10-16T10:00:45Z"),
+                now()
+            ),
+            Some(Duration::from_secs(45))
+        );
+    }
+
+    #[test]
+    fn retry_after_seconds_reset() {
+        assert_eq!(
+            retry_after(&headers("ratelimit-reset", "30"), now()),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn retry_after_unix_time_reset() {
+        let reset = now().as_second() + 60;
+
+        assert_eq!(
+            retry_after(&headers("x-ratelimit-reset", &reset.to_string()), now()),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn retry_after_prefers_retry_after_header() {
+        let mut headers = headers("retry-after", "5");
+        headers.insert("ratelimit-reset", HeaderValue::from_static("60"));
+
+        assert_eq!(retry_after(&headers, now()), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn retry_after_invalid_values() {
+        assert_eq!(retry_after(&headers("retry-after", "soon"), now()), None);
+        assert_eq!(retry_after(&headers("ratelimit-reset", "-5"), now()), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now()), None);
+    }
+
+    // Sends a callback request to the listener and returns the response
+    async fn send_oauth_callback(listener: &TcpListener, path: &str) -> String {
+        let address = listener.local_addr().unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn oauth_callback_returns_code() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let (code, response) = tokio::join!(
+            receive_oauth_callback(&listener, "state123"),
+            send_oauth_callback(&listener, "/callback?code=code123&state=state123"),
+        );
+
+        assert_eq!(code.unwrap(), "code123");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn oauth_callback_rejects_other_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let (code, _) = tokio::join!(
+            receive_oauth_callback(&listener, "state123"),
+            send_oauth_callback(&listener, "/callback?code=code123&state=other"),
+        );
+
+        assert!(matches!(code, Err(ClaudeError::StateMismatch)));
+    }
+
+    fn at_second(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn time_until_refresh_keeps_a_margin_before_the_expiration() {
+        let credentials = credentials("first", 10_000);
+        let margin = TOKEN_REFRESH_MARGIN.as_secs();
+
+        assert_eq!(
+            credentials.time_until_refresh_from(at_second(1_000)),
+            Some(Duration::from_secs(9_000 - margin))
+        );
+        // Within the margin or after the expiration, it is refreshed right away
+        assert_eq!(
+            credentials.time_until_refresh_from(at_second(10_000 - margin + 1)),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            credentials.time_until_refresh_from(at_second(20_000)),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn time_until_refresh_without_known_expiration() {
+        let mut unknown_expiration = credentials("first", 10_000);
+        unknown_expiration.expires_at = None;
+
+        assert_eq!(
+            unknown_expiration.time_until_refresh_from(at_second(1_000)),
+            None
+        );
+
+        // Claude Code refreshes its own credentials
+        let mut claude_code = credentials("first", 10_000);
+        claude_code.source = CredentialSource::ClaudeCode;
+
+        assert_eq!(claude_code.time_until_refresh_from(at_second(1_000)), None);
+    }
+
+    #[test]
+    fn refresh_keeps_the_refresh_token_unless_it_is_rotated() {
+        let credentials = credentials("first", 100);
+
+        let refreshed = with_refreshed_token(
+            &credentials,
+            AnthropicRefreshResponse {
+                access_token: "new".into(),
+                refresh_token: None,
+                expires_in: 3600,
+            },
+        );
+        assert_eq!(refreshed.access_token, "new");
+        assert_eq!(refreshed.refresh_token, "first-refresh");
+        assert_eq!(refreshed.id(), "first");
+        assert!(refreshed.expires_at.unwrap() > 3600);
+
+        let rotated = with_refreshed_token(
+            &credentials,
+            AnthropicRefreshResponse {
+                access_token: "new".into(),
+                refresh_token: Some("rotated".into()),
+                expires_in: 3600,
+            },
+        );
+        assert_eq!(rotated.refresh_token, "rotated");
+    }
+
+    #[test]
+    fn refreshed_elsewhere_reloads_newer_stored_credentials() {
+        let expired = credentials("first", 1_000);
+        let now = at_second(2_000);
+
+        let stored = refreshed_elsewhere(&expired, Some(credentials("first", 10_000)), now);
+        assert_eq!(stored.unwrap().access_token, "first-10000");
+
+        // The same token, or another one that expired too, is refreshed
+        assert!(refreshed_elsewhere(&expired, Some(expired.clone()), now).is_none());
+        assert!(refreshed_elsewhere(&expired, Some(credentials("first", 1_500)), now).is_none());
+        assert!(refreshed_elsewhere(&expired, None, now).is_none());
+    }
+
+    #[test]
+    fn response_error_maps_invalid_grant_to_unauthorized() {
+        let headers = HeaderMap::new();
+
+        assert!(matches!(
+            response_error(
+                StatusCode::BAD_REQUEST,
+                &headers,
+                r#"{"error": "invalid_grant", "error_description": "Invalid refresh token"}"#
+            ),
+            ClaudeError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            response_error(StatusCode::UNAUTHORIZED, &headers, ""),
+            ClaudeError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            response_error(
+                StatusCode::BAD_REQUEST,
+                &headers,
+                r#"{"error": "invalid_request"}"#
+            ),
+            ClaudeError::Http { .. }
+        ));
+    }
 }
@@ -0,0 +1,135 @@
+use log::{info, trace, warn};
+use notify::RecommendedWatcher;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::claude::{AccountInfo, ClaudeCredentials, CredentialSource};
+use crate::error::ClaudeError;
+use crate::utils;
+
+// Scope needed by the usage endpoint.
+const REQUIRED_SCOPE: &str = "user:profile";
+
+const CREDENTIALS_FILE_NAME: &str = ".credentials.json";
+
+// OAuth block written by Claude Code after running `claude /login`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaudeCodeOauth {
+    pub access_token: String,
+    pub refresh_token: String,
+    // Unix timestamp in milliseconds
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+// Content of the Claude Code credentials file.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaudeCodeCredentialsFile {
+    pub claude_ai_oauth: Option<ClaudeCodeOauth>,
+}
+
+// Account logged in Claude Code, kept in its global configuration file.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaudeCodeAccount {
+    pub account_uuid: String,
+    pub email_address: String,
+    pub organization_name: Option<String>,
+}
+
+// Part of the Claude Code global configuration (.claude.json) used by the tray.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaudeCodeConfig {
+    pub oauth_account: Option<ClaudeCodeAccount>,
+}
+
+// Directory where Claude Code stores its configuration. It can be
+// changed with the CLAUDE_CONFIG_DIR environment variable.
+pub fn claude_code_dir() -> Result<PathBuf, String> {
+    if let Ok(config_dir) = std::env::var("CLAUDE_CONFIG_DIR") {
+        return Ok(PathBuf::from(config_dir));
+    }
+
+    let env_home =
+        std::env::var("HOME").map_err(|e| format!("home environment variable not set: {}", e))?;
+
+    Ok(PathBuf::from(env_home).join(".claude"))
+}
+
+// Reads the account logged in Claude Code. It is only used to label the
+// account in the tray, so any error is ignored.
+fn load_account() -> Option<AccountInfo> {
+    let config_file = match std::env::var("CLAUDE_CONFIG_DIR") {
+        Ok(config_dir) => PathBuf::from(config_dir).join(".claude.json"),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".claude.json"),
+    };
+
+    let content = fs::read_to_string(config_file).ok()?;
+    let account = serde_json::from_str::<ClaudeCodeConfig>(&content)
+        .ok()?
+        .oauth_account?;
+
+    Some(AccountInfo {
+        uuid: account.account_uuid,
+        email_address: account.email_address,
+        organization_name: account.organization_name,
+    })
+}
+
+// Reads the credentials of Claude Code. It returns `None` when Claude Code
+// is not logged in or its token cannot be used to read the usage.
+pub fn load_credentials() -> Result<Option<ClaudeCredentials>, ClaudeError> {
+    let credentials_file = claude_code_dir()
+        .map_err(ClaudeError::Io)?
+        .join(CREDENTIALS_FILE_NAME);
+
+    if !credentials_file.exists() {
+        trace!(
+            "claude code credentials not found in {:?}",
+            credentials_file
+        );
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&credentials_file)
+        .map_err(|e| ClaudeError::Io(format!("failed to read claude code credentials: {}", e)))?;
+
+    let credentials: ClaudeCodeCredentialsFile = serde_json::from_str(&content)
+        .map_err(|e| ClaudeError::Parse(format!("error getting claude code credentials: {}", e)))?;
+
+    let Some(oauth) = credentials.claude_ai_oauth else {
+        trace!("claude code is not logged in with a claude.ai account");
+        return Ok(None);
+    };
+
+    if !oauth.scopes.is_empty() && !oauth.scopes.iter().any(|s| s == REQUIRED_SCOPE) {
+        warn!(
+            "claude code credentials don't have the {} scope. ignoring them",
+            REQUIRED_SCOPE
+        );
+        return Ok(None);
+    }
+
+    info!("credentials found in {:?}", credentials_file);
+
+    Ok(Some(ClaudeCredentials {
+        access_token: oauth.access_token,
+        refresh_token: oauth.refresh_token,
+        expires_at: oauth.expires_at.map(|expires_at| expires_at / 1000),
+        account: load_account(),
+        source: CredentialSource::ClaudeCode,
+    }))
+}
+
+// Watches the Claude Code credentials file, which changes after a new
+// login, a logout or a token refresh done by Claude Code.
+pub fn watch_credentials(
+    on_change: impl Fn() + Send + 'static,
+) -> Result<RecommendedWatcher, String> {
+    utils::watch_file(&claude_code_dir()?.join(CREDENTIALS_FILE_NAME), on_change)
+}
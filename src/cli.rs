@@ -0,0 +1,395 @@
+use clap::{Parser, Subcommand};
+use jiff::{SignedDuration, Timestamp};
+use log::{LevelFilter, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::bar::BarFormat;
+use crate::claude::{self, ClaudeCredentials, ClaudeUsageResponse, CredentialSource};
+use crate::config::Config;
+use crate::error::ClaudeError;
+use crate::export::{self, ExportFormat, TimeBound};
+use crate::history::UsageHistory;
+use crate::i18n::Text;
+use crate::utils;
+
+// Command line arguments. Without a subcommand, the tray is started.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file [default: ~/.config/claude-tray/config.toml]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Log level (off, error, warn, info, debug or trace), overriding the configuration
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the current usage of every logged account
+    Status {
+        /// Print the usage as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Log in a Claude account and store its credentials
+    Login,
+    /// Log out an account and remove its stored credentials
+    Logout {
+        /// Identifier or email of the account, needed when several are logged in
+        account: Option<String>,
+    },
+    /// Run the tray (default)
+    Daemon,
+    /// Print the usage continuously for a status bar, without a tray
+    Bar {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = BarFormat::Waybar)]
+        format: BarFormat,
+    },
+    /// Export the recorded usage history
+    Export {
+        /// Start of the export, as a local date (2026-01-31) or a time
+        /// (2026-01-31T10:00:00Z) [default: first sample]
+        #[arg(long, value_name = "TIME")]
+        from: Option<TimeBound>,
+        /// End of the export, included. Dates include the whole day [default: now]
+        #[arg(long, value_name = "TIME")]
+        to: Option<TimeBound>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// File to write the samples to [default: standard output]
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+// Usage of an account printed by the status command.
+#[derive(Debug, Serialize)]
+struct AccountStatus {
+    id: String,
+    label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<ClaudeUsageResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Prints the usage of every account once. It fails with the error of
+// the last account when the usage of no account could be read.
+pub async fn status(config: &Config, json: bool) -> Result<(), ClaudeError> {
+    let accounts = claude::get_local_credentials().await?;
+    let mut statuses = Vec::new();
+    let mut last_error = None;
+
+    for credentials in accounts {
+        let (usage, error) = match account_usage(&credentials).await {
+            Ok(usage) => (Some(usage), None),
+            Err(e) => {
+                let error = e.to_string();
+                last_error = Some(e);
+                (None, Some(error))
+            }
+        };
+
+        statuses.push(AccountStatus {
+            id: credentials.id(),
+            label: credentials.label(),
+            usage,
+            error,
+        });
+    }
+
+    if json {
+        let output = serde_json::to_string_pretty(&statuses)
+            .map_err(|e| ClaudeError::Parse(format!("failed to serialize usage: {}", e)))?;
+        println!("{}", output);
+    } else {
+        print_status(&statuses, config);
+    }
+
+    match last_error {
+        Some(error) if statuses.iter().all(|status| status.usage.is_none()) => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn print_status(statuses: &[AccountStatus], config: &Config) {
+    let language = config.language();
+
+    for (index, status) in statuses.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        println!("{}", status.label);
+
+        if let Some(error) = &status.error {
+            println!("  error: {}", error);
+        }
+
+        let Some(usage) = &status.usage else {
+            continue;
+        };
+
+        for bucket in usage.buckets() {
+            println!(
+                "  {}",
+                utils::format_period(
+                    &language.period_label(&bucket.key, &bucket.label),
+                    bucket.period.utilization,
+                    bucket.period.resets_at,
+                    language,
+                )
+            );
+        }
+
+        if let Some(credits) = utils::format_extra_usage(&usage.extra_usage, language) {
+            println!("  {}: {}", language.text(Text::ExtraUsage), credits);
+        }
+    }
+}
+
+// Reads the usage of an account, refreshing its token once
+// if it is rejected, as the tracking task does.
+async fn account_usage(
+    credentials: &ClaudeCredentials,
+) -> Result<ClaudeUsageResponse, ClaudeError> {
+    match claude::get_usage(&credentials.access_token).await {
+        Err(ClaudeError::Unauthorized(e)) => {
+            warn!("usage request rejected: {}. refreshing access token", e);
+
+            let refreshed = claude::refresh_credentials(credentials).await?;
+            claude::get_usage(&refreshed.access_token).await
+        }
+        result => result,
+    }
+}
+
+// Runs the OAuth login from a terminal. The authorization url is printed, so
+// it can be opened in another machine when running through SSH.
+pub async fn login(config: &Config) -> Result<(), ClaudeError> {
+    let authorization = claude::OAuthAuthorization::new(config.oauth_redirect_port);
+
+    println!(
+        "Open this url in a browser to log in:\n\n{}\n",
+        authorization.auth_url
+    );
+    println!(
+        "Waiting for the authorization on localhost:{port}. Through SSH, forward the port \
+         first (ssh -L {port}:localhost:{port} ...).",
+        port = config.oauth_redirect_port
+    );
+
+    // The browser is only opened in graphical sessions, so text
+    // browsers don't take over the terminal
+    let is_graphical =
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some();
+
+    if is_graphical && let Err(e) = webbrowser::open(&authorization.auth_url) {
+        warn!("failed to open browser: {}", e);
+    }
+
+    let tokens = authorization.finish().await?;
+    let credentials = claude::save_credentials_locally(&tokens).await?;
+
+    println!("Logged in as {}", credentials.label());
+
+    Ok(())
+}
+
+// Writes the usage samples recorded between `from` and `to`
+// to a file, or to the standard output.
+pub fn export(
+    from: Option<TimeBound>,
+    to: Option<TimeBound>,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> Result<(), String> {
+    let path = UsageHistory::default_path()?;
+
+    if !path.exists() {
+        return Err(format!("no usage history found in {:?}", path));
+    }
+
+    let (from, to) = export_range(from, to, Timestamp::now())?;
+
+    let samples = UsageHistory::open(&path)?.samples(None, from, to)?;
+
+    match output {
+        Some(output) => {
+            export::write_file(&samples, format, output)?;
+            eprintln!("{} samples exported to {}", samples.len(), output.display());
+        }
+        None => export::write(&samples, format, &mut std::io::stdout().lock())?,
+    }
+
+    Ok(())
+}
+
+// Range of the exported samples. Without bounds, every sample
+// recorded until `now` is exported.
+fn export_range(
+    from: Option<TimeBound>,
+    to: Option<TimeBound>,
+    now: Timestamp,
+) -> Result<(Timestamp, Timestamp), String> {
+    let from = match from {
+        Some(from) => from.start()?,
+        None => Timestamp::UNIX_EPOCH,
+    };
+    let to = match to {
+        Some(to) => to.end()?,
+        None => now + SignedDuration::from_secs(1),
+    };
+
+    if from >= to {
+        return Err("the start of the export must be before its end".into());
+    }
+
+    Ok((from, to))
+}
+
+// Logs out an account, chosen by its identifier or email. It can be
+// omitted when there is a single account.
+pub async fn logout(account: Option<&str>) -> Result<(), ClaudeError> {
+    let credentials = logout_account(claude::get_local_credentials().await?, account)?;
+
+    // Only the local copy is deleted: the tokens are not revoked in the OAuth server
+    claude::delete_local_credentials(&credentials.id()).await?;
+
+    println!("Logged out {}", credentials.label());
+
+    Ok(())
+}
+
+// Account to log out between the logged ones. The account of Claude Code
+// cannot be logged out, since it would be loaded again from its file.
+fn logout_account(
+    accounts: Vec<ClaudeCredentials>,
+    account: Option<&str>,
+) -> Result<ClaudeCredentials, ClaudeError> {
+    let credentials = match account {
+        Some(account) => accounts
+            .into_iter()
+            .find(|credentials| {
+                credentials.id() == account
+                    || credentials
+                        .account
+                        .as_ref()
+                        .is_some_and(|info| info.email_address == account)
+            })
+            .ok_or(ClaudeError::InvalidAccount(format!(
+                "account {} not found",
+                account
+            )))?,
+        None if accounts.len() == 1 => accounts.into_iter().next().expect("one account"),
+        None => {
+            let labels: Vec<_> = accounts
+                .iter()
+                .map(|credentials| format!("  {} ({})", credentials.id(), credentials.label()))
+                .collect();
+
+            return Err(ClaudeError::InvalidAccount(format!(
+                "several accounts are logged in. choose one of them:\n{}",
+                labels.join("\n")
+            )));
+        }
+    };
+
+    if credentials.source == CredentialSource::ClaudeCode {
+        return Err(ClaudeError::InvalidAccount(format!(
+            "{} is the account of Claude Code. log out from Claude Code instead",
+            credentials.label()
+        )));
+    }
+
+    Ok(credentials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret_service::tests::credentials;
+
+    fn accounts() -> Vec<ClaudeCredentials> {
+        let mut claude_code = credentials("claude-code", 100);
+        claude_code.source = CredentialSource::ClaudeCode;
+
+        vec![
+            credentials("first", 100),
+            credentials("second", 100),
+            claude_code,
+        ]
+    }
+
+    fn logout_id(accounts: Vec<ClaudeCredentials>, account: Option<&str>) -> String {
+        logout_account(accounts, account).unwrap().id()
+    }
+
+    #[test]
+    fn logout_account_by_id_or_email() {
+        assert_eq!(logout_id(accounts(), Some("second")), "second");
+        assert_eq!(logout_id(accounts(), Some("first@example.com")), "first");
+        assert!(matches!(
+            logout_account(accounts(), Some("third")),
+            Err(ClaudeError::InvalidAccount(_))
+        ));
+    }
+
+    #[test]
+    fn logout_account_must_be_chosen_between_several() {
+        assert_eq!(logout_id(vec![credentials("first", 100)], None), "first");
+
+        let Err(ClaudeError::InvalidAccount(message)) = logout_account(accounts(), None) else {
+            panic!("an account was chosen between several");
+        };
+        assert!(message.contains("  first (first@example.com)"));
+        assert!(message.contains("  second (second@example.com)"));
+    }
+
+    #[test]
+    fn logout_account_rejects_claude_code() {
+        assert!(matches!(
+            logout_account(accounts(), Some("claude-code")),
+            Err(ClaudeError::InvalidAccount(message)) if message.contains("Claude Code")
+        ));
+    }
+
+    #[test]
+    fn export_range_bounds() {
+        let now: Timestamp = "2026-10-16T12:00:00Z".parse().unwrap();
+        let time = |time: &str| time.parse::<TimeBound>().unwrap();
+
+        assert_eq!(
+            export_range(None, None, now),
+            Ok((
+                Timestamp::UNIX_EPOCH,
+                "2026-10-16T12:00:01Z".parse().unwrap()
+            ))
+        );
+        assert_eq!(
+            export_range(
+                Some(time("2026-10-01T00:00:00Z")),
+                Some(time("2026-10-02T00:00:00Z")),
+                now
+            ),
+            Ok((
+                "2026-10-01T00:00:00Z".parse().unwrap(),
+                "2026-10-02T00:00:01Z".parse().unwrap()
+            ))
+        );
+        assert!(
+            export_range(
+                Some(time("2026-10-02T00:00:00Z")),
+                Some(time("2026-10-01T00:00:00Z")),
+                now
+            )
+            .is_err()
+        );
+    }
+}
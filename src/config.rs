@@ -0,0 +1,397 @@
+use log::{LevelFilter, error, trace};
+use notify::RecommendedWatcher;
+use reqwest::Proxy;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder};
+use std::net::SocketAddr;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::claude::{DEFAULT_CLAUDE_URL, DEFAULT_OAUTH_REDIRECT_PORT, DEFAULT_USER_AGENT};
+use crate::i18n::Language;
+use crate::icon::UsageThresholds;
+use crate::metrics::DEFAULT_METRICS_ADDRESS;
+use crate::notifications::{DEFAULT_HYSTERESIS, DEFAULT_THRESHOLDS};
+use crate::utils;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+// Limits of the time between usage polls, so the API is not flooded
+// and the usage shown doesn't get too old.
+const MIN_POLL_INTERVAL_MINS: u64 = 1;
+const MAX_POLL_INTERVAL_MINS: u64 = 24 * 60;
+
+// User configuration, read from ~/.config/claude-tray/config.toml.
+// Every option is optional and falls back to its default value.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Minutes between usage polls
+    pub poll_interval_mins: u64,
+    // Localhost port that receives the OAuth callback after login
+    pub oauth_redirect_port: u16,
+    // Page opened by the "Open Claude" option
+    pub claude_url: String,
+    pub log_level: LevelFilter,
+    // Language of the menu and the notifications. The language of the
+    // session is used when it is not set
+    pub language: Option<Language>,
+    // Utilization levels used to color the icon
+    pub icon: UsageThresholds,
+    pub notifications: NotificationsConfig,
+    pub metrics: MetricsConfig,
+    pub history: HistoryConfig,
+    pub retry: RetryConfig,
+    pub http: HttpConfig,
+}
+
+// Usage notifications options.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    pub enabled: bool,
+    // Utilization percentages that trigger a notification when crossed
+    pub thresholds: Vec<f32>,
+    // Points the utilization needs to go below a threshold to notify it again
+    pub hysteresis: f32,
+}
+
+// Prometheus/OpenMetrics endpoint options.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    // Address of the endpoint. It only listens on localhost by default
+    pub address: SocketAddr,
+}
+
+// Usage history options.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    // Days the usage samples are kept
+    pub retention_days: u32,
+    // Days after which the samples are reduced to one every hour
+    pub compact_after_days: u32,
+}
+
+// Retries of the failed usage polls. The delay doubles after every failed
+// attempt, and the usage is polled at the usual interval once they are used.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    // Seconds before the first retry
+    pub initial_delay_secs: u64,
+    // Maximum seconds between retries
+    pub max_delay_secs: u64,
+    pub max_attempts: u32,
+}
+
+// Options of the HTTP client shared by every request.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    // Proxy of every request (e.g. "http://proxy.example.com:3128"). The
+    // proxy environment variables are used when it is not set
+    pub proxy: Option<String>,
+    // Comma separated hosts that don't go through the proxy
+    pub no_proxy: Option<String>,
+    // PEM file with additional root certificates (e.g. of a corporate CA)
+    pub ca_bundle: Option<PathBuf>,
+    pub connect_timeout_secs: u64,
+    // Seconds a whole request can take, including reading the response
+    pub timeout_secs: u64,
+    // Seconds an idle connection is kept open to be reused
+    pub pool_idle_timeout_secs: u64,
+    pub pool_max_idle_per_host: usize,
+    pub user_agent: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            poll_interval_mins: 5,
+            oauth_redirect_port: DEFAULT_OAUTH_REDIRECT_PORT,
+            claude_url: DEFAULT_CLAUDE_URL.into(),
+            log_level: LevelFilter::Info,
+            language: None,
+            icon: UsageThresholds::default(),
+            notifications: NotificationsConfig::default(),
+            metrics: MetricsConfig::default(),
+            history: HistoryConfig::default(),
+            retry: RetryConfig::default(),
+            http: HttpConfig::default(),
+        }
+    }
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            thresholds: DEFAULT_THRESHOLDS.to_vec(),
+            hysteresis: DEFAULT_HYSTERESIS,
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: DEFAULT_METRICS_ADDRESS
+                .parse()
+                .expect("valid metrics address"),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 90,
+            compact_after_days: 7,
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_secs: 10,
+            max_delay_secs: 300,
+            max_attempts: 6,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+            connect_timeout_secs: 10,
+            timeout_secs: 30,
+            pool_idle_timeout_secs: 90,
+            pool_max_idle_per_host: 4,
+            user_agent: DEFAULT_USER_AGENT.into(),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn pool_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.pool_idle_timeout_secs)
+    }
+}
+
+impl RetryConfig {
+    pub fn initial_delay(&self) -> Duration {
+        Duration::from_secs(self.initial_delay_secs)
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_secs(self.max_delay_secs)
+    }
+}
+
+impl Config {
+    // Directory of the tray configuration, shared with the credentials file.
+    pub fn dir() -> Result<PathBuf, String> {
+        let env_home = std::env::var("HOME")
+            .map_err(|e| format!("home environment variable not set: {}", e))?;
+
+        Ok(PathBuf::from(env_home).join(".config/claude-tray"))
+    }
+
+    pub fn default_path() -> Result<PathBuf, String> {
+        Ok(Self::dir()?.join(CONFIG_FILE_NAME))
+    }
+
+    // Reads and validates the configuration file. The default
+    // configuration is used when the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            trace!("configuration not found in {:?}. using defaults", path);
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read configuration {:?}: {}", path, e))?;
+
+        let config: Self = toml::from_str(&content)
+            .map_err(|e| format!("invalid configuration {:?}: {}", path, e))?;
+
+        config
+            .validate()
+            .map_err(|e| format!("invalid configuration {:?}: {}", path, e))?;
+
+        Ok(config)
+    }
+
+    // Reads the configuration, falling back to the default one when it cannot
+    // be read, so a broken file doesn't prevent the tray from starting.
+    pub fn load_or_default(path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+
+        Self::load(path).unwrap_or_else(|e| {
+            error!("{}. using the default configuration", e);
+            Self::default()
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !(MIN_POLL_INTERVAL_MINS..=MAX_POLL_INTERVAL_MINS).contains(&self.poll_interval_mins) {
+            return Err(format!(
+                "poll_interval_mins must be between {} and {}",
+                MIN_POLL_INTERVAL_MINS, MAX_POLL_INTERVAL_MINS
+            ));
+        }
+
+        if self.oauth_redirect_port == 0 {
+            return Err("oauth_redirect_port cannot be 0".into());
+        }
+
+        if !self.claude_url.starts_with("https://") && !self.claude_url.starts_with("http://") {
+            return Err("claude_url must be an http or https url".into());
+        }
+
+        let is_percentage = |value: f32| (0.0..=100.0).contains(&value);
+
+        if !is_percentage(self.icon.warning) || !is_percentage(self.icon.critical) {
+            return Err("icon thresholds must be between 0 and 100".into());
+        }
+
+        if self.icon.warning > self.icon.critical {
+            return Err("icon warning threshold cannot be above the critical one".into());
+        }
+
+        if !self
+            .notifications
+            .thresholds
+            .iter()
+            .all(|t| is_percentage(*t))
+        {
+            return Err("notification thresholds must be between 0 and 100".into());
+        }
+
+        if !is_percentage(self.notifications.hysteresis) {
+            return Err("notification hysteresis must be between 0 and 100".into());
+        }
+
+        if self.history.retention_days == 0 || self.history.compact_after_days == 0 {
+            return Err("history days must be at least 1".into());
+        }
+
+        if self.history.compact_after_days > self.history.retention_days {
+            return Err("history compact_after_days cannot be above retention_days".into());
+        }
+
+        if self.retry.initial_delay_secs == 0 {
+            return Err("retry initial_delay_secs must be at least 1".into());
+        }
+
+        if self.retry.initial_delay_secs > self.retry.max_delay_secs {
+            return Err("retry initial_delay_secs cannot be above max_delay_secs".into());
+        }
+
+        if self.http.connect_timeout_secs == 0 || self.http.timeout_secs == 0 {
+            return Err("http timeouts must be at least 1 second".into());
+        }
+
+        // Only the syntax is checked. The ca bundle is read when the client is built
+        if let Some(proxy) = &self.http.proxy {
+            Proxy::all(proxy).map_err(|e| format!("invalid http proxy {}: {}", proxy, e))?;
+        }
+
+        if self
+            .http
+            .ca_bundle
+            .as_ref()
+            .is_some_and(|path| path.as_os_str().is_empty())
+        {
+            return Err("http ca_bundle cannot be empty".into());
+        }
+
+        Ok(())
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_mins(self.poll_interval_mins)
+    }
+
+    pub fn language(&self) -> Language {
+        self.language.unwrap_or_else(Language::from_env)
+    }
+}
+
+// Watches the configuration file, which doesn't need to exist yet.
+pub fn watch(
+    path: &Path,
+    on_change: impl Fn() + Send + 'static,
+) -> Result<RecommendedWatcher, String> {
+    let config_dir = path
+        .parent()
+        .ok_or(format!("invalid configuration path {:?}", path))?;
+
+    // The directory is watched, so it must exist before the file is created
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(config_dir)
+        .map_err(|e| format!("failed to create {:?}: {}", config_dir, e))?;
+
+    utils::watch_file(path, on_change)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_config(proxy: Option<&str>, ca_bundle: Option<&str>) -> Config {
+        Config {
+            http: HttpConfig {
+                proxy: proxy.map(String::from),
+                ca_bundle: ca_bundle.map(PathBuf::from),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_http_options_without_reading_the_ca_bundle() {
+        assert!(
+            http_config(Some("http://proxy.example.com:3128"), None)
+                .validate()
+                .is_ok()
+        );
+        // The file is only read when the client is built
+        assert!(
+            http_config(None, Some("/nonexistent/ca.pem"))
+                .validate()
+                .is_ok()
+        );
+
+        assert!(
+            http_config(Some("http://[invalid"), None)
+                .validate()
+                .is_err()
+        );
+        assert!(http_config(None, Some("")).validate().is_err());
+    }
+}
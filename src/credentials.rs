@@ -0,0 +1,346 @@
+use log::{info, trace, warn};
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use tokio::sync::OnceCell;
+
+use crate::claude::ClaudeCredentials;
+use crate::config::Config;
+use crate::error::ClaudeError;
+use crate::secret_service::SecretServiceStore;
+
+// Content of a credentials store. Versions without multi-account
+// support saved a single account.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StoredCredentials {
+    Accounts(Vec<ClaudeCredentials>),
+    Single(ClaudeCredentials),
+}
+
+// Parses the content of a credentials store into the list of accounts.
+pub fn parse_credentials(content: &[u8]) -> Result<Vec<ClaudeCredentials>, ClaudeError> {
+    let credentials = serde_json::from_slice(content)
+        .map_err(|e| ClaudeError::Parse(format!("error getting credentials: {}", e)))?;
+
+    Ok(match credentials {
+        StoredCredentials::Accounts(accounts) => accounts,
+        StoredCredentials::Single(account) => vec![account],
+    })
+}
+
+// Credentials backend that stores the tokens in a json file only
+// readable by the user. It is used when no keyring is available.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    // Default location of the credentials file
+    // ($HOME/.config/claude-tray/credentials.json).
+    pub fn default_path() -> Result<PathBuf, ClaudeError> {
+        Ok(Config::dir()
+            .map_err(ClaudeError::Io)?
+            .join("credentials.json"))
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn load(&self) -> Result<Vec<ClaudeCredentials>, ClaudeError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        trace!("reading credentials file located in {:?}", self.path);
+
+        // Files written by older versions used the default permissions
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600)).map_err(|e| {
+            ClaudeError::Io(format!(
+                "failed to restrict credentials file permissions: {}",
+                e
+            ))
+        })?;
+
+        let credentials = fs::read(&self.path)
+            .map_err(|e| ClaudeError::Io(format!("failed to read credentials file: {}", e)))?;
+
+        parse_credentials(&credentials)
+    }
+
+    // Writes the credentials with 0600 permissions. The content is written to a
+    // temporary file first and then renamed, so a crash never leaves a truncated file.
+    pub fn save(&self, credentials: &[ClaudeCredentials]) -> Result<(), ClaudeError> {
+        let config_dir = self
+            .path
+            .parent()
+            .ok_or(ClaudeError::Io("invalid credentials file location".into()))?;
+
+        trace!("saving credentials to {:?}", config_dir);
+
+        if !config_dir.exists() {
+            info!("credentials file not exists. creating new file");
+
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(config_dir)
+                .map_err(|e| {
+                    ClaudeError::Io(format!("failed to create config directory: {}", e))
+                })?;
+        }
+
+        let json_fmt = serde_json::to_string_pretty(credentials)
+            .map_err(|e| ClaudeError::Io(format!("failed to serialize credentials: {}", e)))?;
+
+        let temporary_file = self.path.with_extension("json.tmp");
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary_file)
+            .map_err(|e| ClaudeError::Io(format!("failed to create credentials file: {}", e)))?;
+
+        file.write_all(json_fmt.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| ClaudeError::Io(format!("failed to write credentials file: {}", e)))?;
+
+        fs::rename(&temporary_file, &self.path)
+            .map_err(|e| ClaudeError::Io(format!("failed to replace credentials file: {}", e)))?;
+
+        Ok(())
+    }
+
+    pub fn delete(&self) -> Result<(), ClaudeError> {
+        if self.path.exists() {
+            fs::remove_file(&self.path).map_err(|e| {
+                ClaudeError::Io(format!("failed to remove credentials file: {}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+// Store shared by every access to the credentials. It is opened once, so the
+// session bus is connected to and the file is migrated only at the first access.
+static SHARED_STORE: OnceCell<CredentialStore> = OnceCell::const_new();
+
+// Place where the credentials are persisted. The keyring is preferred
+// and the file is only used when no Secret Service provider is running.
+#[derive(Debug, Clone)]
+pub enum CredentialStore {
+    SecretService(SecretServiceStore),
+    File(FileStore),
+}
+
+impl CredentialStore {
+    // Store used by the whole process, opened the first time it is needed. A
+    // failed opening is not kept, so it is attempted again at the next access.
+    pub async fn shared() -> Result<&'static Self, ClaudeError> {
+        SHARED_STORE.get_or_try_init(Self::open).await
+    }
+
+    // Opens the best available store. When the keyring is available, the
+    // credentials of the plaintext file are migrated into it.
+    async fn open() -> Result<Self, ClaudeError> {
+        let file_store = FileStore::new(FileStore::default_path()?);
+
+        let keyring = match SecretServiceStore::connect().await {
+            Ok(keyring) => keyring,
+            Err(e) => {
+                warn!(
+                    "secret service not available, using credentials file: {}",
+                    e
+                );
+                return Ok(Self::File(file_store));
+            }
+        };
+
+        if file_store.exists()
+            && let Err(e) = migrate_file_to_keyring(&file_store, &keyring).await
+        {
+            warn!(
+                "failed to migrate credentials into the secret service: {}",
+                e
+            );
+            return Ok(Self::File(file_store));
+        }
+
+        Ok(Self::SecretService(keyring))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SecretService(_) => "secret service",
+            Self::File(_) => "credentials file",
+        }
+    }
+
+    pub async fn load(&self) -> Result<Vec<ClaudeCredentials>, ClaudeError> {
+        match self {
+            Self::SecretService(store) => store.load().await,
+            Self::File(store) => store.load(),
+        }
+    }
+
+    pub async fn save(&self, credentials: &[ClaudeCredentials]) -> Result<(), ClaudeError> {
+        match self {
+            Self::SecretService(store) => store.save(credentials).await,
+            Self::File(store) => store.save(credentials),
+        }
+    }
+
+    pub async fn delete(&self) -> Result<(), ClaudeError> {
+        match self {
+            Self::SecretService(store) => store.delete().await,
+            Self::File(store) => store.delete(),
+        }
+    }
+}
+
+// Moves the credentials stored by previous versions into the keyring. The
+// file is removed only after the keyring accepted the credentials.
+async fn migrate_file_to_keyring(
+    file_store: &FileStore,
+    keyring: &SecretServiceStore,
+) -> Result<(), ClaudeError> {
+    info!("migrating credentials file into the secret service");
+
+    let file_credentials = file_store.load()?;
+
+    if !file_credentials.is_empty() {
+        let credentials = merge_credentials(keyring.load().await?, file_credentials);
+        keyring.save(&credentials).await?;
+    }
+
+    file_store.delete()
+}
+
+// Joins the accounts of two stores. The file can be newer than the keyring
+// when the tray ran without it for a while, so the credentials that expire
+// later are kept for the accounts found in both.
+fn merge_credentials(
+    mut credentials: Vec<ClaudeCredentials>,
+    others: Vec<ClaudeCredentials>,
+) -> Vec<ClaudeCredentials> {
+    for other in others {
+        match credentials
+            .iter_mut()
+            .find(|stored| stored.id() == other.id())
+        {
+            Some(stored) => {
+                if other.expires_at > stored.expires_at {
+                    *stored = other;
+                }
+            }
+            None => credentials.push(other),
+        }
+    }
+
+    credentials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret_service::tests::{credentials, fake_store};
+
+    // Credentials file in its own temporary directory, with the given accounts
+    fn file_store(name: &str, credentials: &[ClaudeCredentials]) -> FileStore {
+        let dir =
+            std::env::temp_dir().join(format!("claude-tray-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+
+        let store = FileStore::new(dir.join("credentials.json"));
+        store.save(credentials).unwrap();
+        store
+    }
+
+    fn access_tokens(credentials: &[ClaudeCredentials]) -> Vec<&str> {
+        credentials
+            .iter()
+            .map(|credentials| credentials.access_token.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn migrate_into_empty_keyring() {
+        let (keyring, _provider) = fake_store().await;
+        let file_store = file_store(
+            "empty-keyring",
+            &[credentials("first", 100), credentials("second", 100)],
+        );
+
+        migrate_file_to_keyring(&file_store, &keyring)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            access_tokens(&keyring.load().await.unwrap()),
+            ["first-100", "second-100"]
+        );
+        assert!(!file_store.exists());
+    }
+
+    #[tokio::test]
+    async fn migrate_keeps_newer_file_credentials() {
+        let (keyring, _provider) = fake_store().await;
+        keyring
+            .save(&[credentials("first", 100), credentials("second", 300)])
+            .await
+            .unwrap();
+        let file_store = file_store(
+            "newer-file",
+            &[
+                credentials("first", 200),
+                credentials("second", 200),
+                credentials("third", 100),
+            ],
+        );
+
+        migrate_file_to_keyring(&file_store, &keyring)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            access_tokens(&keyring.load().await.unwrap()),
+            ["first-200", "second-300", "third-100"]
+        );
+        assert!(!file_store.exists());
+    }
+
+    #[tokio::test]
+    async fn migrate_empty_file() {
+        let (keyring, _provider) = fake_store().await;
+        keyring.save(&[credentials("first", 100)]).await.unwrap();
+        let file_store = file_store("empty-file", &[]);
+
+        migrate_file_to_keyring(&file_store, &keyring)
+            .await
+            .unwrap();
+
+        assert_eq!(access_tokens(&keyring.load().await.unwrap()), ["first-100"]);
+        assert!(!file_store.exists());
+    }
+
+    #[test]
+    fn merge_replaces_credentials_without_expiration() {
+        let mut old = credentials("first", 100);
+        old.expires_at = None;
+
+        let merged = merge_credentials(vec![old], vec![credentials("first", 100)]);
+
+        assert_eq!(access_tokens(&merged), ["first-100"]);
+    }
+}
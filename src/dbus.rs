@@ -0,0 +1,277 @@
+use log::trace;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use zbus::{Connection, fdo, interface, object_server::SignalEmitter};
+
+use crate::TrayMessage;
+
+pub const SERVICE_NAME: &str = "org.jrdx0.ClaudeTray";
+pub const OBJECT_PATH: &str = "/org/jrdx0/ClaudeTray";
+
+// Values exported through the D-Bus service, updated after every usage poll.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServiceState {
+    // Identifier and label of every logged account
+    pub accounts: Vec<(String, String)>,
+    // Account identifier, field name, name, utilization and reset time
+    // (Unix seconds, 0 when unknown) of every usage period
+    pub usage: Vec<(String, String, String, f64, i64)>,
+    // Highest utilization between all the accounts
+    pub utilization: f64,
+    // Time of the last usage poll in Unix seconds, 0 before the first one
+    pub last_updated: i64,
+    // Whether a login is waiting for the browser authorization. It is not
+    // exported, but new logins are rejected until it finishes
+    pub is_logging_in: bool,
+    // Identifier of the account shared with Claude Code, if it is tracked. It is
+    // not exported, but it cannot be logged out, as it is loaded again from its file
+    pub claude_code_account: Option<String>,
+}
+
+impl ServiceState {
+    fn auth_state(&self) -> &'static str {
+        if self.accounts.is_empty() {
+            "logged-out"
+        } else {
+            "logged-in"
+        }
+    }
+}
+
+// org.jrdx0.ClaudeTray interface. Its methods are handled by
+// the tray through the messages channel.
+struct ClaudeTrayInterface {
+    state: ServiceState,
+    notifier: mpsc::Sender<TrayMessage>,
+}
+
+impl ClaudeTrayInterface {
+    // Sends a message without waiting for room in the channel. The tray updates
+    // this interface while it handles the messages, so waiting here while the
+    // interface is borrowed would block both sides.
+    fn send(&self, message: TrayMessage) -> fdo::Result<()> {
+        self.notifier.try_send(message).map_err(|e| match e {
+            TrySendError::Full(_) => fdo::Error::Failed("busy".into()),
+            TrySendError::Closed(_) => fdo::Error::Failed("the tray is not running".into()),
+        })
+    }
+}
+
+#[interface(name = "org.jrdx0.ClaudeTray")]
+impl ClaudeTrayInterface {
+    // Polls the usage of every account right away
+    fn refresh(&self) -> fdo::Result<()> {
+        self.send(TrayMessage::Refresh)
+    }
+
+    // Starts the OAuth login in the browser to add an account
+    fn login(&self) -> fdo::Result<()> {
+        if self.state.is_logging_in {
+            return Err(fdo::Error::Failed("a login is already in progress".into()));
+        }
+
+        self.send(TrayMessage::Login)
+    }
+
+    fn logout(&self, account_id: String) -> fdo::Result<()> {
+        if !self.state.accounts.iter().any(|(id, _)| *id == account_id) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "account {} not found",
+                account_id
+            )));
+        }
+
+        if self.state.claude_code_account.as_ref() == Some(&account_id) {
+            return Err(fdo::Error::NotSupported(format!(
+                "account {} is managed by Claude Code; log out from Claude Code",
+                account_id
+            )));
+        }
+
+        self.send(TrayMessage::Logout(account_id))
+    }
+
+    // "logged-in" when any account is tracked, "logged-out" otherwise
+    #[zbus(property)]
+    fn auth_state(&self) -> String {
+        self.state.auth_state().into()
+    }
+
+    #[zbus(property)]
+    fn accounts(&self) -> Vec<(String, String)> {
+        self.state.accounts.clone()
+    }
+
+    #[zbus(property)]
+    fn usage(&self) -> Vec<(String, String, String, f64, i64)> {
+        self.state.usage.clone()
+    }
+
+    #[zbus(property)]
+    fn utilization(&self) -> f64 {
+        self.state.utilization
+    }
+
+    #[zbus(property)]
+    fn last_updated(&self) -> i64 {
+        self.state.last_updated
+    }
+}
+
+// Session bus service exposing the usage to other desktop components.
+#[derive(Debug, Clone)]
+pub struct DbusService {
+    connection: Connection,
+}
+
+impl DbusService {
+    // Owns the service name and serves the tray object. It fails when
+    // another instance of the tray already owns the name.
+    pub async fn start(notifier: mpsc::Sender<TrayMessage>) -> Result<Self, String> {
+        let interface = ClaudeTrayInterface {
+            state: ServiceState::default(),
+            notifier,
+        };
+
+        let connection = zbus::connection::Builder::session()
+            .and_then(|builder| builder.name(SERVICE_NAME))
+            .and_then(|builder| builder.serve_at(OBJECT_PATH, interface))
+            .map_err(|e| format!("failed to configure the d-bus service: {}", e))?
+            .build()
+            .await
+            .map_err(|e| format!("failed to start the d-bus service: {}", e))?;
+
+        trace!("d-bus service {} started", SERVICE_NAME);
+
+        Ok(Self { connection })
+    }
+
+    // Replaces the exported values, emitting PropertiesChanged
+    // for the properties that changed.
+    pub async fn update(&self, state: ServiceState) -> Result<(), String> {
+        let interface_ref = self
+            .connection
+            .object_server()
+            .interface::<_, ClaudeTrayInterface>(OBJECT_PATH)
+            .await
+            .map_err(|e| format!("d-bus interface not found: {}", e))?;
+
+        let mut interface = interface_ref.get_mut().await;
+
+        if interface.state == state {
+            return Ok(());
+        }
+
+        let previous = std::mem::replace(&mut interface.state, state);
+        let emitter: &SignalEmitter<'_> = interface_ref.signal_emitter();
+
+        let result = async {
+            if previous.auth_state() != interface.state.auth_state() {
+                interface.auth_state_changed(emitter).await?;
+            }
+            if previous.accounts != interface.state.accounts {
+                interface.accounts_changed(emitter).await?;
+            }
+            if previous.usage != interface.state.usage {
+                interface.usage_changed(emitter).await?;
+            }
+            if previous.utilization != interface.state.utilization {
+                interface.utilization_changed(emitter).await?;
+            }
+            if previous.last_updated != interface.state.last_updated {
+                interface.last_updated_changed(emitter).await?;
+            }
+
+            zbus::Result::Ok(())
+        };
+
+        result
+            .await
+            .map_err(|e| format!("failed to emit d-bus properties changes: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::p2p_connections;
+    use std::time::Duration;
+
+    fn interface(notifier: mpsc::Sender<TrayMessage>) -> ClaudeTrayInterface {
+        ClaudeTrayInterface {
+            state: ServiceState {
+                accounts: vec![
+                    ("claude-code".into(), "Claude Code".into()),
+                    ("tray".into(), "tray@example.com".into()),
+                ],
+                claude_code_account: Some("claude-code".into()),
+                ..Default::default()
+            },
+            notifier,
+        }
+    }
+
+    #[tokio::test]
+    async fn logout_rejects_the_claude_code_account() {
+        let (notifier, mut messages) = mpsc::channel(1);
+        let interface = interface(notifier);
+
+        assert!(matches!(
+            interface.logout("claude-code".into()),
+            Err(fdo::Error::NotSupported(_))
+        ));
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn logout_sends_the_account_of_the_tray() {
+        let (notifier, mut messages) = mpsc::channel(1);
+        let interface = interface(notifier);
+
+        interface.logout("tray".into()).unwrap();
+
+        assert!(matches!(
+            messages.try_recv(),
+            Ok(TrayMessage::Logout(account_id)) if account_id == "tray"
+        ));
+        assert!(matches!(
+            interface.logout("unknown".into()),
+            Err(fdo::Error::InvalidArgs(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn methods_fail_while_the_messages_channel_is_full() {
+        let (notifier, mut messages) = mpsc::channel(1);
+        notifier.try_send(TrayMessage::Refresh).unwrap();
+
+        let (client, server) = p2p_connections().await;
+        server
+            .object_server()
+            .at(OBJECT_PATH, interface(notifier))
+            .await
+            .unwrap();
+        let service = DbusService { connection: server };
+        let proxy = zbus::Proxy::new(&client, SERVICE_NAME, OBJECT_PATH, SERVICE_NAME)
+            .await
+            .unwrap();
+
+        // The tray updates the interface before it reads the next message
+        let (refresh, update) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                proxy.call_method("Refresh", &()),
+                service.update(ServiceState::default())
+            )
+        })
+        .await
+        .expect("the method call and the update blocked each other");
+
+        assert!(matches!(
+            fdo::Error::from(refresh.unwrap_err()),
+            fdo::Error::Failed(message) if message == "busy"
+        ));
+        update.unwrap();
+
+        assert!(matches!(messages.try_recv(), Ok(TrayMessage::Refresh)));
+        assert!(messages.try_recv().is_err());
+    }
+}
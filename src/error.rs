@@ -0,0 +1,84 @@
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+use crate::claude::ClaudeErrorResponse;
+
+// Errors of the requests to the Claude API and the OAuth server, and of the
+// local resources they need. The usage tracking reacts differently to each one.
+#[derive(Debug, Clone)]
+pub enum ClaudeError {
+    // The request could not be sent or its response could not be read
+    Network(String),
+    // Unsuccessful response without an API error in its body
+    Http {
+        status: StatusCode,
+        body: String,
+    },
+    // Error returned by the API
+    Api {
+        status: StatusCode,
+        response: ClaudeErrorResponse,
+    },
+    // The credentials were rejected, so a new login is needed
+    Unauthorized(String),
+    // Too many requests, or the server is overloaded (429 or 503).
+    // `retry_after` is the time the server asks to wait
+    RateLimited {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    // The response doesn't have the expected format
+    Parse(String),
+    // Local files, sockets or the browser failed
+    Io(String),
+    // The Secret Service keyring failed or is not available
+    Keyring(String),
+    // The OAuth callback was not received before the login expired
+    Timeout(String),
+    // The OAuth callback belongs to another login, so it is rejected
+    StateMismatch,
+    // The account asked for doesn't exist, is ambiguous or cannot be used
+    InvalidAccount(String),
+    // The configuration doesn't allow the operation (e.g. the OAuth port is taken)
+    Config(String),
+}
+
+impl fmt::Display for ClaudeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http { status, body } => {
+                write!(f, "request failed with status {}: {}", status, body)
+            }
+            Self::Api { status, response } => write!(
+                f,
+                "api error ({}, {}): {} [request_id: {}]",
+                status, response.error.error_type, response.error.message, response.request_id
+            ),
+            Self::Unauthorized(message) => write!(f, "unauthorized: {}", message),
+            Self::StateMismatch => write!(f, "oauth callback state doesn't match the login"),
+            Self::RateLimited {
+                status,
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "rate limited ({}). retry after {}s",
+                status,
+                retry_after.as_secs()
+            ),
+            Self::RateLimited {
+                status,
+                retry_after: None,
+            } => write!(f, "rate limited ({})", status),
+            Self::Network(message)
+            | Self::Parse(message)
+            | Self::Io(message)
+            | Self::Keyring(message)
+            | Self::Timeout(message)
+            | Self::InvalidAccount(message)
+            | Self::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ClaudeError {}
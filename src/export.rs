@@ -0,0 +1,462 @@
+use clap::ValueEnum;
+use jiff::{SignedDuration, Timestamp, civil::Date, tz::TimeZone};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{DirBuilder, File};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::claude::{ExtraUsage, KNOWN_PERIODS, UsagePeriod};
+use crate::history::{UsageHistory, UsageSample};
+
+// Formats the usage history can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Comma separated values, one row for every sample
+    Csv,
+    /// JSON array with every sample
+    Json,
+    /// One JSON object for every sample, in a line each
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+// Sample with the structure of the usage response, plus the account and the poll time.
+#[derive(Debug, Serialize)]
+struct SampleRecord<'a> {
+    recorded_at: Timestamp,
+    account_id: &'a str,
+    email: &'a str,
+    organization: Option<&'a str>,
+    // Known periods are always present. Unknown periods are
+    // only present in the samples that have them
+    #[serde(flatten)]
+    periods: BTreeMap<&'a str, Option<&'a UsagePeriod>>,
+    extra_usage: &'a ExtraUsage,
+}
+
+impl<'a> SampleRecord<'a> {
+    fn new(sample: &'a UsageSample) -> Self {
+        let mut periods: BTreeMap<&str, Option<&UsagePeriod>> =
+            KNOWN_PERIODS.into_iter().map(|key| (key, None)).collect();
+
+        for (key, usage) in &sample.periods {
+            periods.insert(key, Some(usage));
+        }
+
+        Self {
+            recorded_at: sample.recorded_at,
+            account_id: &sample.account_id,
+            email: &sample.email,
+            organization: sample.organization.as_deref(),
+            periods,
+            extra_usage: &sample.extra_usage,
+        }
+    }
+}
+
+// Bound of the exported time range, given as a local date or an exact time.
+#[derive(Debug, Clone, Copy)]
+pub enum TimeBound {
+    Date(Date),
+    Time(Timestamp),
+}
+
+impl FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(timestamp) = value.parse::<Timestamp>() {
+            return Ok(Self::Time(timestamp));
+        }
+
+        value.parse::<Date>().map(Self::Date).map_err(|_| {
+            format!(
+                "invalid time {}. use a date (2026-01-31) or a time (2026-01-31T10:00:00Z)",
+                value
+            )
+        })
+    }
+}
+
+impl TimeBound {
+    // First second included in the range when it starts at this bound
+    pub fn start(self) -> Result<Timestamp, String> {
+        match self {
+            Self::Time(timestamp) => Ok(timestamp),
+            Self::Date(date) => local_midnight(date),
+        }
+    }
+
+    // First second excluded from the range when it ends at this bound.
+    // Dates include their whole day
+    pub fn end(self) -> Result<Timestamp, String> {
+        match self {
+            Self::Time(timestamp) => timestamp
+                .checked_add(SignedDuration::from_secs(1))
+                .map_err(|e| format!("invalid time {}: {}", timestamp, e)),
+            Self::Date(date) => local_midnight(
+                date.tomorrow()
+                    .map_err(|e| format!("invalid date {}: {}", date, e))?,
+            ),
+        }
+    }
+}
+
+fn local_midnight(date: Date) -> Result<Timestamp, String> {
+    date.to_zoned(TimeZone::system())
+        .map(|zoned| zoned.timestamp())
+        .map_err(|e| format!("invalid date {}: {}", date, e))
+}
+
+// File the tray exports the history to, next to the history database
+// (e.g. ~/.local/share/claude-tray/exports/claude-usage-20260131-103000.csv).
+pub fn default_path(format: ExportFormat, now: Timestamp) -> Result<PathBuf, String> {
+    let history_path = UsageHistory::default_path()?;
+    let data_dir = history_path
+        .parent()
+        .ok_or(format!("invalid usage history path {:?}", history_path))?;
+
+    let file_name = format!(
+        "claude-usage-{}.{}",
+        now.to_zoned(TimeZone::system()).strftime("%Y%m%d-%H%M%S"),
+        format.extension()
+    );
+
+    Ok(data_dir.join("exports").join(file_name))
+}
+
+// Writes the samples to a file, creating its directory when it doesn't exist.
+pub fn write_file(
+    samples: &[UsageSample],
+    format: ExportFormat,
+    path: &Path,
+) -> Result<(), String> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| format!("failed to create {:?}: {}", dir, e))?;
+    }
+
+    let file = File::create(path).map_err(|e| format!("failed to create {:?}: {}", path, e))?;
+    let mut output = BufWriter::new(file);
+
+    write(samples, format, &mut output)?;
+
+    output
+        .flush()
+        .map_err(|e| format!("failed to write {:?}: {}", path, e))
+}
+
+// Writes the samples in the given format. Every usage period known by this
+// version has its own column or field, named as in the usage response, even
+// when it is absent. Unknown periods are added after them.
+pub fn write(
+    samples: &[UsageSample],
+    format: ExportFormat,
+    output: &mut impl Write,
+) -> Result<(), String> {
+    let result = match format {
+        ExportFormat::Csv => write_csv(samples, output),
+        ExportFormat::Json => {
+            let records: Vec<_> = samples.iter().map(SampleRecord::new).collect();
+
+            serde_json::to_writer_pretty(&mut *output, &records)
+                .map_err(std::io::Error::from)
+                .and_then(|_| writeln!(output))
+        }
+        ExportFormat::Ndjson => samples.iter().try_for_each(|sample| {
+            serde_json::to_writer(&mut *output, &SampleRecord::new(sample))
+                .map_err(std::io::Error::from)
+                .and_then(|_| writeln!(output))
+        }),
+    };
+
+    result.map_err(|e| format!("failed to export usage history: {}", e))
+}
+
+// Columns of the account, the known periods and the extra usage come first,
+// so they keep their position when unknown periods are added at the end
+fn write_csv(samples: &[UsageSample], output: &mut impl Write) -> std::io::Result<()> {
+    let other_periods: Vec<&str> = samples
+        .iter()
+        .flat_map(|sample| sample.periods.keys())
+        .map(String::as_str)
+        .filter(|key| !KNOWN_PERIODS.contains(key))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let period_columns = |periods: &[&str]| -> Vec<String> {
+        periods
+            .iter()
+            .flat_map(|period| {
+                [
+                    format!("{}_utilization", period),
+                    format!("{}_resets_at", period),
+                ]
+            })
+            .collect()
+    };
+
+    let mut header: Vec<String> = ["recorded_at", "account_id", "email", "organization"]
+        .map(String::from)
+        .into();
+    header.extend(period_columns(&KNOWN_PERIODS));
+    header.extend(
+        [
+            "extra_usage_is_enabled",
+            "extra_usage_monthly_limit",
+            "extra_usage_used_credits",
+            "extra_usage_utilization",
+        ]
+        .map(String::from),
+    );
+    header.extend(period_columns(&other_periods));
+
+    writeln!(output, "{}", header.join(","))?;
+
+    for sample in samples {
+        let period_values = |periods: &[&str]| -> Vec<String> {
+            periods
+                .iter()
+                .flat_map(|period| {
+                    let usage = sample.periods.get(*period);
+                    [
+                        optional(usage.map(|usage| usage.utilization)),
+                        optional(usage.and_then(|usage| usage.resets_at)),
+                    ]
+                })
+                .collect()
+        };
+
+        let mut row = vec![
+            sample.recorded_at.to_string(),
+            sample.account_id.clone(),
+            sample.email.clone(),
+            optional(sample.organization.as_ref()),
+        ];
+        row.extend(period_values(&KNOWN_PERIODS));
+        row.extend([
+            sample.extra_usage.is_enabled.to_string(),
+            optional(sample.extra_usage.monthly_limit),
+            optional(sample.extra_usage.used_credits),
+            optional(sample.extra_usage.utilization),
+        ]);
+        row.extend(period_values(&other_periods));
+
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        writeln!(output, "{}", row.join(","))?;
+    }
+
+    Ok(())
+}
+
+// Empty CSV field for absent values
+fn optional(value: Option<impl ToString>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Quotes a CSV field when it contains separators, quotes or line breaks
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn period(utilization: f32, resets_at: Option<&str>) -> UsagePeriod {
+        UsagePeriod {
+            utilization,
+            resets_at: resets_at.map(|resets_at| resets_at.parse().unwrap()),
+        }
+    }
+
+    // A sample with an unknown period, and a newer one with another unknown
+    // period and without the seven day period
+    fn samples() -> Vec<UsageSample> {
+        vec![
+            UsageSample {
+                account_id: "first".into(),
+                email: "first@example.com".into(),
+                organization: Some("Example, Inc".into()),
+                recorded_at: "2026-10-16T10:00:00Z".parse().unwrap(),
+                periods: BTreeMap::from([
+                    (
+                        "five_hour".into(),
+                        period(23.0, Some("2026-10-16T14:00:00Z")),
+                    ),
+                    ("seven_day".into(), period(41.0, None)),
+                    ("two_hour".into(), period(80.0, None)),
+                ]),
+                extra_usage: ExtraUsage {
+                    is_enabled: true,
+                    monthly_limit: Some(5000),
+                    used_credits: Some(1250),
+                    utilization: Some(25.0),
+                },
+            },
+            UsageSample {
+                account_id: "second".into(),
+                email: "second@example.com".into(),
+                organization: None,
+                recorded_at: "2026-10-16T10:05:00Z".parse().unwrap(),
+                periods: BTreeMap::from([
+                    (
+                        "five_hour".into(),
+                        period(24.0, Some("2026-10-16T14:00:00Z")),
+                    ),
+                    ("seven_day_haiku".into(), period(15.0, None)),
+                ]),
+                extra_usage: ExtraUsage::default(),
+            },
+        ]
+    }
+
+    fn export(format: ExportFormat) -> String {
+        let mut output = Vec::new();
+        write(&samples(), format, &mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys: Vec<_> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn csv_columns_keep_their_order() {
+        let csv = export(ExportFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(
+            lines,
+            [
+                "recorded_at,account_id,email,organization,\
+                 five_hour_utilization,five_hour_resets_at,\
+                 seven_day_utilization,seven_day_resets_at,\
+                 seven_day_oauth_apps_utilization,seven_day_oauth_apps_resets_at,\
+                 seven_day_opus_utilization,seven_day_opus_resets_at,\
+                 seven_day_sonnet_utilization,seven_day_sonnet_resets_at,\
+                 iguana_necktie_utilization,iguana_necktie_resets_at,\
+                 seven_day_iguana_necktie_utilization,seven_day_iguana_necktie_resets_at,\
+                 extra_usage_is_enabled,extra_usage_monthly_limit,\
+                 extra_usage_used_credits,extra_usage_utilization,\
+                 seven_day_haiku_utilization,seven_day_haiku_resets_at,\
+                 two_hour_utilization,two_hour_resets_at",
+                "2026-10-16T10:00:00Z,first,first@example.com,\"Example, Inc\",\
+                 23,2026-10-16T14:00:00Z,41,,,,,,,,,,,,\
+                 true,5000,1250,25,\
+                 ,,80,",
+                "2026-10-16T10:05:00Z,second,second@example.com,,\
+                 24,2026-10-16T14:00:00Z,,,,,,,,,,,,,\
+                 false,,,,\
+                 15,,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_fields_are_named_as_the_usage_response() {
+        let json: Value = serde_json::from_str(&export(ExportFormat::Json)).unwrap();
+        let records = json.as_array().unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            keys(&records[0]),
+            [
+                "account_id",
+                "email",
+                "extra_usage",
+                "five_hour",
+                "iguana_necktie",
+                "organization",
+                "recorded_at",
+                "seven_day",
+                "seven_day_iguana_necktie",
+                "seven_day_oauth_apps",
+                "seven_day_opus",
+                "seven_day_sonnet",
+                "two_hour",
+            ]
+        );
+        assert_eq!(keys(&records[0]["five_hour"]), ["resets_at", "utilization"]);
+        assert_eq!(
+            keys(&records[0]["extra_usage"]),
+            ["is_enabled", "monthly_limit", "used_credits", "utilization"]
+        );
+        assert_eq!(records[0]["recorded_at"], "2026-10-16T10:00:00Z");
+
+        // Absent known periods are null and unknown ones are left out
+        assert_eq!(records[1]["seven_day"], Value::Null);
+        assert_eq!(records[1]["seven_day_haiku"]["utilization"], 15.0);
+        assert!(records[1].get("two_hour").is_none());
+    }
+
+    #[test]
+    fn ndjson_has_a_json_record_in_every_line() {
+        let json: Value = serde_json::from_str(&export(ExportFormat::Json)).unwrap();
+        let ndjson = export(ExportFormat::Ndjson);
+
+        let records: Vec<Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(json.as_array().unwrap(), &records);
+    }
+
+    #[test]
+    fn time_bound_includes_its_second() {
+        let time: TimeBound = "2026-01-31T10:00:00Z".parse().unwrap();
+
+        assert_eq!(time.start(), Ok("2026-01-31T10:00:00Z".parse().unwrap()));
+        assert_eq!(time.end(), Ok("2026-01-31T10:00:01Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn date_bound_includes_its_whole_day() {
+        let date: TimeBound = "2026-01-31".parse().unwrap();
+        let next_date: TimeBound = "2026-02-01".parse().unwrap();
+
+        assert_eq!(date.end(), next_date.start());
+    }
+
+    #[test]
+    fn time_bound_end_at_the_last_time() {
+        assert!(TimeBound::Time(Timestamp::MAX).end().is_err());
+        assert!(TimeBound::Date(Date::MAX).end().is_err());
+    }
+
+    #[test]
+    fn time_bound_rejects_invalid_values() {
+        assert!("2026-13-01".parse::<TimeBound>().is_err());
+        assert!("yesterday".parse::<TimeBound>().is_err());
+    }
+}
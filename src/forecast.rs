@@ -0,0 +1,302 @@
+use jiff::{SignedDuration, Timestamp};
+use std::collections::HashMap;
+
+use crate::claude::ClaudeUsageResponse;
+
+// Usage periods forecasted, by their field name, with the time the consumption rate
+// is smoothed over. Older polls weigh less the longer the time passed since them.
+const FORECAST_PERIODS: [(&str, SignedDuration); 2] = [
+    ("five_hour", SignedDuration::from_mins(30)),
+    ("seven_day", SignedDuration::from_hours(6)),
+];
+
+// Time the usage needs to be observed before forecasting, so a
+// couple of close polls don't give a misleading projection
+const MIN_OBSERVED_TIME: SignedDuration = SignedDuration::from_mins(10);
+
+// A period reset when its reset time moves further than this
+const RESET_TOLERANCE: SignedDuration = SignedDuration::from_mins(1);
+
+// Time a usage period is projected to reach its limit, before it resets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunOut {
+    pub runs_out_at: Timestamp,
+    pub resets_at: Timestamp,
+}
+
+// Consumption rate of a usage period since it last reset.
+#[derive(Debug)]
+struct PeriodRate {
+    utilization: f32,
+    recorded_at: Timestamp,
+    resets_at: Option<Timestamp>,
+    // Smoothed utilization points consumed per second
+    rate: f64,
+    observed: SignedDuration,
+    // Whether the run out of the period was already notified
+    is_notified: bool,
+}
+
+impl PeriodRate {
+    fn new(utilization: f32, recorded_at: Timestamp, resets_at: Option<Timestamp>) -> Self {
+        Self {
+            utilization,
+            recorded_at,
+            resets_at,
+            rate: 0.0,
+            observed: SignedDuration::ZERO,
+            is_notified: false,
+        }
+    }
+
+    // Time the period reaches 100% at the current rate, if it happens before it resets
+    fn run_out(&self) -> Option<RunOut> {
+        let resets_at = self.resets_at?;
+
+        if self.observed < MIN_OBSERVED_TIME || self.rate <= 0.0 || self.utilization >= 100.0 {
+            return None;
+        }
+
+        let seconds_left = (100.0 - self.utilization as f64) / self.rate;
+        let runs_out_at = self
+            .recorded_at
+            .checked_add(SignedDuration::try_from_secs_f64(seconds_left).ok()?)
+            .ok()?;
+
+        (runs_out_at < resets_at).then_some(RunOut {
+            runs_out_at,
+            resets_at,
+        })
+    }
+}
+
+// Projects when the current session and the weekly limits of an account
+// run out, from the consumption rate between its usage polls.
+#[derive(Debug, Default)]
+pub struct UsageForecast {
+    // Keyed by usage period field name
+    periods: HashMap<String, PeriodRate>,
+}
+
+impl UsageForecast {
+    // Adds a usage poll to the consumption rates. It returns the field name, name
+    // and projection of the periods that are now on pace to run out before they
+    // reset, once for every reset.
+    pub fn update(
+        &mut self,
+        usage: &ClaudeUsageResponse,
+        recorded_at: Timestamp,
+    ) -> Vec<(String, String, RunOut)> {
+        let mut run_outs = Vec::new();
+
+        for bucket in usage.buckets() {
+            let Some((_, smoothing_time)) =
+                FORECAST_PERIODS.iter().find(|(key, _)| *key == bucket.key)
+            else {
+                continue;
+            };
+
+            let utilization = bucket.period.utilization;
+            let resets_at = bucket.period.resets_at;
+
+            let Some(state) = self.periods.get_mut(&bucket.key) else {
+                self.periods.insert(
+                    bucket.key.clone(),
+                    PeriodRate::new(utilization, recorded_at, resets_at),
+                );
+                continue;
+            };
+
+            let elapsed = recorded_at.duration_since(state.recorded_at);
+            let is_reset = utilization < state.utilization
+                || match (state.resets_at, resets_at) {
+                    (Some(previous), Some(current)) => {
+                        current.duration_since(previous).abs() > RESET_TOLERANCE
+                    }
+                    (previous, current) => previous != current,
+                };
+
+            // The rate starts over with every period
+            if is_reset {
+                *state = PeriodRate::new(utilization, recorded_at, resets_at);
+                continue;
+            }
+
+            if elapsed <= SignedDuration::ZERO {
+                continue;
+            }
+
+            let current_rate = (utilization - state.utilization) as f64 / elapsed.as_secs_f64();
+            // Exponential smoothing weighted by the time between polls
+            let weight = 1.0 - (-elapsed.as_secs_f64() / smoothing_time.as_secs_f64()).exp();
+
+            state.rate = if state.observed.is_zero() {
+                current_rate
+            } else {
+                state.rate + weight * (current_rate - state.rate)
+            };
+            state.observed += elapsed;
+            state.utilization = utilization;
+            state.recorded_at = recorded_at;
+            state.resets_at = resets_at;
+
+            if let Some(run_out) = state.run_out()
+                && !state.is_notified
+            {
+                state.is_notified = true;
+                run_outs.push((bucket.key.clone(), bucket.label.clone(), run_out));
+            }
+        }
+
+        run_outs
+    }
+
+    // Current projection of a usage period, if it runs out before it resets
+    pub fn run_out(&self, key: &str) -> Option<RunOut> {
+        self.periods.get(key).and_then(PeriodRate::run_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claude::tests::usage;
+
+    fn start() -> Timestamp {
+        "2026-10-16T10:00:00Z".parse().unwrap()
+    }
+
+    fn resets_at() -> Timestamp {
+        start() + SignedDuration::from_hours(5)
+    }
+
+    // Adds a five hour poll `mins` minutes after the start
+    fn poll(
+        forecast: &mut UsageForecast,
+        mins: i64,
+        utilization: f32,
+    ) -> Vec<(String, String, RunOut)> {
+        forecast.update(
+            &usage(utilization, resets_at()),
+            start() + SignedDuration::from_mins(mins),
+        )
+    }
+
+    fn rate(forecast: &UsageForecast) -> f64 {
+        forecast.periods["five_hour"].rate
+    }
+
+    #[test]
+    fn update_smooths_the_rate_by_the_time_between_polls() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        poll(&mut forecast, 10, 20.0);
+        // The first rate is taken as it is: 10 points in 10 minutes
+        assert!((rate(&forecast) - 1.0 / 60.0).abs() < 1e-9);
+
+        poll(&mut forecast, 20, 20.0);
+        // 10 minutes of a 30 minutes smoothing time
+        let expected = 1.0 / 60.0 * (-1.0f64 / 3.0).exp();
+        assert!((rate(&forecast) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn run_out_at_the_current_pace() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        assert!(poll(&mut forecast, 5, 15.0).is_empty());
+        // Not observed for long enough yet
+        assert_eq!(forecast.run_out("five_hour"), None);
+
+        let run_outs = poll(&mut forecast, 10, 20.0);
+
+        // 80 points left at 1 point per minute
+        let expected = RunOut {
+            runs_out_at: start() + SignedDuration::from_mins(90),
+            resets_at: resets_at(),
+        };
+        assert_eq!(forecast.run_out("five_hour"), Some(expected));
+        assert_eq!(
+            run_outs,
+            [("five_hour".into(), "Current session".into(), expected)]
+        );
+    }
+
+    #[test]
+    fn no_run_out_after_the_reset() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        poll(&mut forecast, 30, 11.0);
+
+        // 89 points left at 1 point every 30 minutes
+        assert_eq!(forecast.run_out("five_hour"), None);
+    }
+
+    #[test]
+    fn no_run_out_when_the_rate_is_zero() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        assert!(poll(&mut forecast, 10, 10.0).is_empty());
+        assert!(poll(&mut forecast, 20, 10.0).is_empty());
+
+        assert_eq!(rate(&forecast), 0.0);
+        assert_eq!(forecast.run_out("five_hour"), None);
+    }
+
+    #[test]
+    fn update_notifies_the_run_out_once() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        assert_eq!(poll(&mut forecast, 10, 20.0).len(), 1);
+        assert!(poll(&mut forecast, 20, 30.0).is_empty());
+        assert!(poll(&mut forecast, 30, 40.0).is_empty());
+
+        assert!(forecast.run_out("five_hour").is_some());
+    }
+
+    #[test]
+    fn update_starts_over_when_the_utilization_drops() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        assert_eq!(poll(&mut forecast, 10, 20.0).len(), 1);
+
+        poll(&mut forecast, 20, 5.0);
+        assert_eq!(rate(&forecast), 0.0);
+        assert_eq!(forecast.run_out("five_hour"), None);
+
+        // The run out of the new period is notified again
+        assert_eq!(poll(&mut forecast, 30, 15.0).len(), 1);
+    }
+
+    #[test]
+    fn update_starts_over_when_the_reset_time_changes() {
+        let mut forecast = UsageForecast::default();
+
+        poll(&mut forecast, 0, 10.0);
+        poll(&mut forecast, 10, 20.0);
+
+        // Moving the reset time within the tolerance keeps the rate
+        let close_reset = resets_at() + SignedDuration::from_secs(30);
+        forecast.update(
+            &usage(25.0, close_reset),
+            start() + SignedDuration::from_mins(15),
+        );
+        assert!(forecast.run_out("five_hour").is_some());
+
+        let next_reset = resets_at() + SignedDuration::from_hours(5);
+        let run_outs = forecast.update(
+            &usage(30.0, next_reset),
+            start() + SignedDuration::from_mins(20),
+        );
+
+        assert!(run_outs.is_empty());
+        assert_eq!(forecast.periods["five_hour"].resets_at, Some(next_reset));
+        assert_eq!(forecast.run_out("five_hour"), None);
+    }
+}
@@ -0,0 +1,704 @@
+use jiff::{SignedDuration, Timestamp, civil::Date, tz::TimeZone};
+use log::{info, trace};
+use rusqlite::{Connection, params};
+use std::collections::BTreeMap;
+use std::fs::DirBuilder;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::claude::{ClaudeCredentials, ClaudeUsageResponse, ExtraUsage, UsagePeriod};
+use crate::config::HistoryConfig;
+
+const HISTORY_FILE_NAME: &str = "history.sqlite3";
+
+// Time the tray and the command line wait for each other to write
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Every poll is a sample, with the utilization of each one of its periods.
+// Timestamps are stored as Unix seconds.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS samples (
+        id INTEGER PRIMARY KEY,
+        account_id TEXT NOT NULL,
+        email TEXT NOT NULL,
+        organization TEXT,
+        recorded_at INTEGER NOT NULL,
+        extra_usage_is_enabled INTEGER NOT NULL,
+        extra_usage_monthly_limit INTEGER,
+        extra_usage_used_credits INTEGER,
+        extra_usage_utilization REAL
+    );
+
+    CREATE INDEX IF NOT EXISTS samples_recorded_at ON samples (recorded_at, account_id);
+
+    CREATE TABLE IF NOT EXISTS sample_periods (
+        sample_id INTEGER NOT NULL REFERENCES samples (id) ON DELETE CASCADE,
+        period TEXT NOT NULL,
+        utilization REAL NOT NULL,
+        resets_at INTEGER,
+        PRIMARY KEY (sample_id, period)
+    ) WITHOUT ROWID;
+
+    PRAGMA user_version = 1;
+";
+
+// Compacted samples keep the highest utilization of every hour, so the
+// peaks before a reset are not lost
+const COMPACT_SAMPLES: &str = "
+    DELETE FROM samples
+    WHERE recorded_at < ?1 AND id NOT IN (
+        SELECT id FROM (
+            SELECT
+                samples.id,
+                ROW_NUMBER() OVER (
+                    PARTITION BY samples.account_id, samples.recorded_at / 3600
+                    ORDER BY MAX(sample_periods.utilization) DESC, samples.id DESC
+                ) AS position
+            FROM samples
+            LEFT JOIN sample_periods ON sample_periods.sample_id = samples.id
+            WHERE samples.recorded_at < ?1
+            GROUP BY samples.id
+        )
+        WHERE position = 1
+    )
+";
+
+// Samples with their periods, one row for every period, in the order they were recorded
+const SELECT_SAMPLES: &str = "
+    SELECT
+        samples.id, samples.account_id, samples.email, samples.organization,
+        samples.recorded_at, samples.extra_usage_is_enabled, samples.extra_usage_monthly_limit,
+        samples.extra_usage_used_credits, samples.extra_usage_utilization,
+        sample_periods.period, sample_periods.utilization, sample_periods.resets_at
+    FROM samples
+    LEFT JOIN sample_periods ON sample_periods.sample_id = samples.id
+    WHERE samples.recorded_at >= ?1 AND samples.recorded_at < ?2
+        AND (?3 IS NULL OR samples.account_id = ?3)
+    ORDER BY samples.recorded_at, samples.id
+";
+
+// Cells of the sparklines of the last day (one every hour)
+// and the last week (one every 6 hours)
+const DAY_CELLS: usize = 24;
+const WEEK_CELLS: usize = 28;
+
+// Days shown in the daily peaks
+const PEAK_DAYS: usize = 7;
+
+// Usage of an account received in a poll.
+#[derive(Debug, Clone)]
+pub struct UsageSample {
+    pub account_id: String,
+    pub email: String,
+    pub organization: Option<String>,
+    pub recorded_at: Timestamp,
+    // Usage periods by their field name
+    pub periods: BTreeMap<String, UsagePeriod>,
+    pub extra_usage: ExtraUsage,
+}
+
+impl UsageSample {
+    pub fn new(
+        credentials: &ClaudeCredentials,
+        usage: &ClaudeUsageResponse,
+        recorded_at: Timestamp,
+    ) -> Self {
+        Self {
+            account_id: credentials.id(),
+            email: credentials
+                .account
+                .as_ref()
+                .map(|info| info.email_address.clone())
+                .unwrap_or_default(),
+            organization: credentials
+                .account
+                .as_ref()
+                .and_then(|info| info.organization_name.clone()),
+            recorded_at,
+            periods: usage
+                .buckets()
+                .into_iter()
+                .map(|bucket| (bucket.key, bucket.period.clone()))
+                .collect(),
+            extra_usage: usage.extra_usage.clone(),
+        }
+    }
+}
+
+// Recent usage of an account, summarized for the tray menu.
+#[derive(Debug, Clone, Default)]
+pub struct HistorySummary {
+    // Highest session utilization of every hour of the last day
+    pub day: Vec<Option<f32>>,
+    // Highest weekly utilization every 6 hours of the last week
+    pub week: Vec<Option<f32>>,
+    // Highest session and weekly utilization of every day of
+    // the last week, in local dates and newest first
+    pub daily_peaks: Vec<(Date, f32, f32)>,
+}
+
+impl HistorySummary {
+    pub fn new(samples: &[UsageSample], now: Timestamp) -> Self {
+        Self::in_time_zone(samples, now, TimeZone::system())
+    }
+
+    // Summary with the daily peaks grouped by the dates of `time_zone`
+    fn in_time_zone(samples: &[UsageSample], now: Timestamp, time_zone: TimeZone) -> Self {
+        let mut daily_peaks: BTreeMap<Date, (f32, f32)> = BTreeMap::new();
+
+        for sample in samples {
+            let date = sample.recorded_at.to_zoned(time_zone.clone()).date();
+            let utilization = |key: &str| {
+                sample
+                    .periods
+                    .get(key)
+                    .map(|period| period.utilization)
+                    .unwrap_or_default()
+            };
+
+            let peaks = daily_peaks.entry(date).or_default();
+            peaks.0 = peaks.0.max(utilization("five_hour"));
+            peaks.1 = peaks.1.max(utilization("seven_day"));
+        }
+
+        Self {
+            day: sparkline_values(samples, "five_hour", now, DAY_CELLS, 1),
+            week: sparkline_values(samples, "seven_day", now, WEEK_CELLS, 7),
+            daily_peaks: daily_peaks
+                .into_iter()
+                .rev()
+                .take(PEAK_DAYS)
+                .map(|(date, (five_hour, seven_day))| (date, five_hour, seven_day))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.daily_peaks.is_empty()
+    }
+}
+
+// Highest utilization of a usage period in every cell of the last `days`,
+// `None` for the cells without samples
+fn sparkline_values(
+    samples: &[UsageSample],
+    key: &str,
+    now: Timestamp,
+    cells: usize,
+    days: i64,
+) -> Vec<Option<f32>> {
+    let span = SignedDuration::from_hours(24 * days);
+    let cell_secs = span.as_secs() / cells as i64;
+    let start = now - span;
+    let mut values = vec![None; cells];
+
+    for sample in samples {
+        let Some(period) = sample.periods.get(key) else {
+            continue;
+        };
+
+        let offset = sample.recorded_at.duration_since(start).as_secs();
+        if offset < 0 {
+            continue;
+        }
+
+        let cell = ((offset / cell_secs) as usize).min(cells - 1);
+        values[cell] = Some(values[cell].map_or(period.utilization, |value: f32| {
+            value.max(period.utilization)
+        }));
+    }
+
+    values
+}
+
+// Usage samples of every account, stored in a SQLite database
+// under the XDG data directory.
+#[derive(Debug, Clone)]
+pub struct UsageHistory {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl UsageHistory {
+    // $XDG_DATA_HOME/claude-tray/history.sqlite3, or
+    // ~/.local/share/claude-tray/history.sqlite3 when it is not set.
+    pub fn default_path() -> Result<PathBuf, String> {
+        let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+            Some(data_home) if Path::new(&data_home).is_absolute() => PathBuf::from(data_home),
+            _ => {
+                let env_home = std::env::var("HOME")
+                    .map_err(|e| format!("home environment variable not set: {}", e))?;
+
+                PathBuf::from(env_home).join(".local/share")
+            }
+        };
+
+        Ok(data_dir.join("claude-tray").join(HISTORY_FILE_NAME))
+    }
+
+    // Opens the database, creating it and its directory when they don't exist.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(data_dir) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(data_dir)
+                .map_err(|e| format!("failed to create {:?}: {}", data_dir, e))?;
+        }
+
+        let connection = Connection::open(path)
+            .map_err(|e| format!("failed to open usage history {:?}: {}", path, e))?;
+
+        // Auto vacuum only applies to new databases, so it is set before anything is written
+        connection
+            .pragma_update(None, "auto_vacuum", "INCREMENTAL")
+            .and_then(|_| connection.busy_timeout(BUSY_TIMEOUT))
+            .and_then(|_| connection.pragma_update(None, "journal_mode", "WAL"))
+            .and_then(|_| connection.pragma_update(None, "foreign_keys", true))
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .map_err(|e| format!("failed to initialize usage history {:?}: {}", path, e))?;
+
+        trace!("usage history opened from {:?}", path);
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn record(&self, sample: &UsageSample) -> Result<(), String> {
+        let mut connection = self.connection.lock().expect("history lock poisoned");
+
+        let result = (|| {
+            let transaction = connection.transaction()?;
+
+            transaction.execute(
+                "INSERT INTO samples (
+                    account_id, email, organization, recorded_at, extra_usage_is_enabled,
+                    extra_usage_monthly_limit, extra_usage_used_credits, extra_usage_utilization
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    sample.account_id,
+                    sample.email,
+                    sample.organization,
+                    sample.recorded_at.as_second(),
+                    sample.extra_usage.is_enabled,
+                    sample.extra_usage.monthly_limit,
+                    sample.extra_usage.used_credits,
+                    sample.extra_usage.utilization,
+                ],
+            )?;
+
+            let sample_id = transaction.last_insert_rowid();
+
+            {
+                let mut insert_period = transaction.prepare(
+                    "INSERT INTO sample_periods (sample_id, period, utilization, resets_at)
+                    VALUES (?1, ?2, ?3, ?4)",
+                )?;
+
+                for (period, usage) in &sample.periods {
+                    insert_period.execute(params![
+                        sample_id,
+                        period,
+                        usage.utilization,
+                        usage.resets_at.map(|resets_at| resets_at.as_second()),
+                    ])?;
+                }
+            }
+
+            transaction.commit()
+        })();
+
+        result.map_err(|e| format!("failed to record usage sample: {}", e))
+    }
+
+    // Samples recorded between `from` and `to`, of every account or only one of them
+    pub fn samples(
+        &self,
+        account_id: Option<&str>,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<Vec<UsageSample>, String> {
+        let connection = self.connection.lock().expect("history lock poisoned");
+        let mut samples: Vec<UsageSample> = Vec::new();
+        let mut last_id = None;
+
+        let result = (|| {
+            let mut statement = connection.prepare_cached(SELECT_SAMPLES)?;
+            let mut rows =
+                statement.query(params![from.as_second(), to.as_second(), account_id])?;
+
+            while let Some(row) = rows.next()? {
+                let id: i64 = row.get(0)?;
+
+                if last_id != Some(id) {
+                    last_id = Some(id);
+                    samples.push(UsageSample {
+                        account_id: row.get(1)?,
+                        email: row.get(2)?,
+                        organization: row.get(3)?,
+                        recorded_at: timestamp(row.get(4)?),
+                        periods: BTreeMap::new(),
+                        extra_usage: ExtraUsage {
+                            is_enabled: row.get(5)?,
+                            monthly_limit: row.get(6)?,
+                            used_credits: row.get(7)?,
+                            utilization: row.get(8)?,
+                        },
+                    });
+                }
+
+                let Some(period) = row.get::<_, Option<String>>(9)? else {
+                    continue;
+                };
+                let usage = UsagePeriod {
+                    utilization: row.get(10)?,
+                    resets_at: row.get::<_, Option<i64>>(11)?.map(timestamp),
+                };
+
+                if let Some(sample) = samples.last_mut() {
+                    sample.periods.insert(period, usage);
+                }
+            }
+
+            Ok::<_, rusqlite::Error>(())
+        })();
+
+        result.map_err(|e| format!("failed to read usage history: {}", e))?;
+
+        Ok(samples)
+    }
+
+    // Summary of the usage of an account in the week before `now`
+    pub fn summary(&self, account_id: &str, now: Timestamp) -> Result<HistorySummary, String> {
+        let from = now - SignedDuration::from_hours(24 * PEAK_DAYS as i64);
+        let samples = self.samples(Some(account_id), from, now + SignedDuration::from_secs(1))?;
+
+        Ok(HistorySummary::new(&samples, now))
+    }
+
+    // Deletes the samples older than the retention and reduces the older than
+    // `compact_after_days` to one every hour, releasing the space they used.
+    pub fn compact(&self, config: &HistoryConfig) -> Result<(), String> {
+        self.compact_at(config, Timestamp::now())
+    }
+
+    fn compact_at(&self, config: &HistoryConfig, now: Timestamp) -> Result<(), String> {
+        let connection = self.connection.lock().expect("history lock poisoned");
+        let days_ago = |days: u32| (now - SignedDuration::from_hours(24 * days as i64)).as_second();
+
+        let result = (|| {
+            let deleted = connection.execute(
+                "DELETE FROM samples WHERE recorded_at < ?1",
+                [days_ago(config.retention_days)],
+            )?;
+            let compacted =
+                connection.execute(COMPACT_SAMPLES, [days_ago(config.compact_after_days)])?;
+
+            connection.execute_batch("PRAGMA incremental_vacuum")?;
+
+            Ok::<_, rusqlite::Error>((deleted, compacted))
+        })();
+
+        let (deleted, compacted) =
+            result.map_err(|e| format!("failed to compact usage history: {}", e))?;
+
+        if deleted + compacted > 0 {
+            info!(
+                "usage history compacted: {} samples expired, {} merged",
+                deleted, compacted
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// Timestamps are stored in Unix seconds, always in the valid range
+fn timestamp(seconds: i64) -> Timestamp {
+    Timestamp::from_second(seconds).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::tz;
+
+    fn now() -> Timestamp {
+        "2026-10-16T12:00:00Z".parse().unwrap()
+    }
+
+    // Sample with the given session and weekly utilization
+    fn sample(recorded_at: &str, five_hour: f32, seven_day: f32) -> UsageSample {
+        let period = |utilization| UsagePeriod {
+            utilization,
+            resets_at: None,
+        };
+
+        UsageSample {
+            account_id: "first".into(),
+            email: "first@example.com".into(),
+            organization: None,
+            recorded_at: recorded_at.parse().unwrap(),
+            periods: BTreeMap::from([
+                ("five_hour".into(), period(five_hour)),
+                ("seven_day".into(), period(seven_day)),
+            ]),
+            extra_usage: ExtraUsage::default(),
+        }
+    }
+
+    fn samples() -> Vec<UsageSample> {
+        vec![
+            sample("2026-10-15T23:30:00Z", 40.0, 10.0),
+            sample("2026-10-16T00:30:00Z", 20.0, 12.0),
+            sample("2026-10-16T10:00:00Z", 60.0, 11.0),
+        ]
+    }
+
+    fn date(date: &str) -> Date {
+        date.parse().unwrap()
+    }
+
+    // History in its own temporary directory
+    fn history(name: &str) -> UsageHistory {
+        let dir = std::env::temp_dir().join(format!(
+            "claude-tray-test-{}-history-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        UsageHistory::open(&dir.join(HISTORY_FILE_NAME)).unwrap()
+    }
+
+    fn of_account(account_id: &str, mut sample: UsageSample) -> UsageSample {
+        sample.account_id = account_id.into();
+        sample
+    }
+
+    // Account, time and session utilization of the stored samples
+    fn stored(history: &UsageHistory) -> Vec<(String, String, f32)> {
+        history
+            .samples(None, Timestamp::UNIX_EPOCH, now())
+            .unwrap()
+            .into_iter()
+            .map(|sample| {
+                (
+                    sample.account_id,
+                    sample.recorded_at.to_string(),
+                    sample.periods["five_hour"].utilization,
+                )
+            })
+            .collect()
+    }
+
+    fn count_rows(history: &UsageHistory, table: &str) -> i64 {
+        history
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    fn history_config(retention_days: u32, compact_after_days: u32) -> HistoryConfig {
+        HistoryConfig {
+            enabled: true,
+            retention_days,
+            compact_after_days,
+        }
+    }
+
+    #[test]
+    fn daily_peaks_newest_first() {
+        let summary = HistorySummary::in_time_zone(&samples(), now(), TimeZone::UTC);
+
+        assert_eq!(
+            summary.daily_peaks,
+            [
+                (date("2026-10-16"), 60.0, 12.0),
+                (date("2026-10-15"), 40.0, 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn daily_peaks_in_local_dates() {
+        let time_zone = TimeZone::fixed(tz::offset(-4));
+        let summary = HistorySummary::in_time_zone(&samples(), now(), time_zone);
+
+        // The first two samples are still on the 15th four hours behind UTC
+        assert_eq!(
+            summary.daily_peaks,
+            [
+                (date("2026-10-16"), 60.0, 11.0),
+                (date("2026-10-15"), 40.0, 12.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn daily_peaks_of_the_last_week() {
+        let samples: Vec<_> = (1..=9)
+            .map(|day| sample(&format!("2026-10-{:02}T10:00:00Z", day), 10.0, 10.0))
+            .collect();
+        let summary = HistorySummary::in_time_zone(&samples, now(), TimeZone::UTC);
+
+        assert_eq!(summary.daily_peaks.len(), PEAK_DAYS);
+        assert_eq!(summary.daily_peaks[0].0, date("2026-10-09"));
+        assert_eq!(summary.daily_peaks[6].0, date("2026-10-03"));
+    }
+
+    #[test]
+    fn sparkline_keeps_the_peak_of_every_hour() {
+        let samples = vec![
+            // Older than the last day
+            sample("2026-10-15T11:59:59Z", 90.0, 0.0),
+            sample("2026-10-15T12:10:00Z", 30.0, 0.0),
+            sample("2026-10-15T12:50:00Z", 50.0, 0.0),
+            sample("2026-10-15T12:55:00Z", 40.0, 0.0),
+            sample("2026-10-16T01:00:00Z", 10.0, 0.0),
+            // The poll of the current time goes in the last cell
+            sample("2026-10-16T12:00:00Z", 70.0, 0.0),
+        ];
+
+        let values = sparkline_values(&samples, "five_hour", now(), DAY_CELLS, 1);
+
+        assert_eq!(values.len(), DAY_CELLS);
+        assert_eq!(values[0], Some(50.0));
+        assert_eq!(values[13], Some(10.0));
+        assert_eq!(values[23], Some(70.0));
+        assert_eq!(values.iter().flatten().count(), 3);
+    }
+
+    #[test]
+    fn sparkline_of_the_week_every_6_hours() {
+        let mut without_period = sample("2026-10-10T06:00:00Z", 0.0, 0.0);
+        without_period.periods.remove("seven_day");
+
+        let samples = vec![
+            sample("2026-10-09T17:59:59Z", 20.0, 15.0),
+            sample("2026-10-09T18:00:00Z", 20.0, 25.0),
+            without_period,
+            sample("2026-10-16T11:00:00Z", 20.0, 35.0),
+        ];
+
+        let values = sparkline_values(&samples, "seven_day", now(), WEEK_CELLS, 7);
+
+        assert_eq!(values.len(), WEEK_CELLS);
+        assert_eq!(values[0], Some(15.0));
+        assert_eq!(values[1], Some(25.0));
+        assert_eq!(values[27], Some(35.0));
+        assert_eq!(values.iter().flatten().count(), 3);
+    }
+
+    #[test]
+    fn record_and_read_samples() {
+        let history = history("round-trip");
+        let mut first = sample("2026-10-16T10:00:00Z", 40.0, 10.0);
+        first.organization = Some("Example".into());
+        first.periods.get_mut("five_hour").unwrap().resets_at =
+            Some("2026-10-16T14:00:00Z".parse().unwrap());
+        first.extra_usage = ExtraUsage {
+            is_enabled: true,
+            monthly_limit: Some(5000),
+            used_credits: Some(1250),
+            utilization: Some(25.0),
+        };
+
+        history.record(&first).unwrap();
+        history
+            .record(&of_account(
+                "second",
+                sample("2026-10-16T10:30:00Z", 20.0, 5.0),
+            ))
+            .unwrap();
+        history
+            .record(&sample("2026-10-16T11:00:00Z", 60.0, 11.0))
+            .unwrap();
+
+        let samples = history.samples(None, Timestamp::UNIX_EPOCH, now()).unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].organization.as_deref(), Some("Example"));
+        assert_eq!(
+            samples[0].periods["five_hour"].resets_at,
+            first.periods["five_hour"].resets_at
+        );
+        assert_eq!(samples[0].periods["seven_day"].utilization, 10.0);
+        assert_eq!(samples[0].extra_usage.used_credits, Some(1250));
+        assert_eq!(samples[0].extra_usage.utilization, Some(25.0));
+
+        let second = history
+            .samples(Some("second"), Timestamp::UNIX_EPOCH, now())
+            .unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].account_id, "second");
+
+        // `from` is included and `to` is not
+        let bounded = history
+            .samples(
+                None,
+                "2026-10-16T10:30:00Z".parse().unwrap(),
+                "2026-10-16T11:00:00Z".parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(bounded.len(), 1);
+        assert_eq!(bounded[0].account_id, "second");
+    }
+
+    #[test]
+    fn compact_deletes_expired_samples_with_their_periods() {
+        let history = history("retention");
+        history
+            .record(&sample("2026-10-01T10:00:00Z", 40.0, 10.0))
+            .unwrap();
+        history
+            .record(&sample("2026-10-15T10:00:00Z", 20.0, 12.0))
+            .unwrap();
+
+        history.compact_at(&history_config(7, 7), now()).unwrap();
+
+        assert_eq!(
+            stored(&history),
+            [("first".into(), "2026-10-15T10:00:00Z".into(), 20.0)]
+        );
+        assert_eq!(count_rows(&history, "samples"), 1);
+        assert_eq!(count_rows(&history, "sample_periods"), 2);
+    }
+
+    #[test]
+    fn compact_keeps_the_hourly_peak_of_every_account() {
+        let history = history("compaction");
+        for sample in [
+            sample("2026-10-10T10:05:00Z", 30.0, 10.0),
+            sample("2026-10-10T10:20:00Z", 70.0, 10.0),
+            sample("2026-10-10T10:40:00Z", 50.0, 10.0),
+            of_account("second", sample("2026-10-10T10:10:00Z", 20.0, 10.0)),
+            of_account("second", sample("2026-10-10T10:50:00Z", 25.0, 10.0)),
+            sample("2026-10-10T11:15:00Z", 10.0, 10.0),
+            // Samples newer than `compact_after_days` are kept
+            sample("2026-10-16T10:05:00Z", 30.0, 10.0),
+            sample("2026-10-16T10:20:00Z", 20.0, 10.0),
+        ] {
+            history.record(&sample).unwrap();
+        }
+
+        history.compact_at(&history_config(30, 3), now()).unwrap();
+
+        assert_eq!(
+            stored(&history),
+            [
+                ("first".into(), "2026-10-10T10:20:00Z".into(), 70.0),
+                ("second".into(), "2026-10-10T10:50:00Z".into(), 25.0),
+                ("first".into(), "2026-10-10T11:15:00Z".into(), 10.0),
+                ("first".into(), "2026-10-16T10:05:00Z".into(), 30.0),
+                ("first".into(), "2026-10-16T10:20:00Z".into(), 20.0),
+            ]
+        );
+        assert_eq!(count_rows(&history, "sample_periods"), 10);
+    }
+}
@@ -0,0 +1,110 @@
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use std::fs;
+use std::sync::{LazyLock, RwLock};
+
+use crate::config::HttpConfig;
+
+// Client shared by every request, so the connections are reused between polls.
+// It is replaced every time the HTTP options of the configuration change.
+static CLIENT: LazyLock<RwLock<Client>> = LazyLock::new(|| {
+    RwLock::new(build(&HttpConfig::default()).expect("valid default http client"))
+});
+
+// Shared client. Clones share the same connection pool
+pub fn client() -> Client {
+    CLIENT.read().expect("http client lock poisoned").clone()
+}
+
+// Replaces the shared client with one built from the configuration. The
+// previous client is kept when the new one cannot be built.
+pub fn configure(config: &HttpConfig) -> Result<(), String> {
+    let client = build(config)?;
+
+    *CLIENT.write().expect("http client lock poisoned") = client;
+
+    Ok(())
+}
+
+// Builds a client with the configured proxy, CA bundle, timeouts and pool
+// limits. Without a proxy in the configuration, the HTTPS_PROXY, HTTP_PROXY,
+// ALL_PROXY and NO_PROXY environment variables are used.
+fn build(config: &HttpConfig) -> Result<Client, String> {
+    let mut builder = Client::builder()
+        .user_agent(&config.user_agent)
+        .connect_timeout(config.connect_timeout())
+        .timeout(config.timeout())
+        .pool_idle_timeout(config.pool_idle_timeout())
+        .pool_max_idle_per_host(config.pool_max_idle_per_host);
+
+    if let Some(proxy_url) = &config.proxy {
+        let proxy = Proxy::all(proxy_url)
+            .map_err(|e| format!("invalid proxy {}: {}", proxy_url, e))?
+            .no_proxy(config.no_proxy.as_deref().and_then(NoProxy::from_string));
+
+        builder = builder.proxy(proxy);
+    }
+
+    // The certificates are trusted in addition to the system ones
+    if let Some(path) = &config.ca_bundle {
+        let pem = fs::read(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("invalid ca bundle {:?}: {}", path, e))?;
+
+        if certificates.is_empty() {
+            return Err(format!("no certificates found in {:?}", path));
+        }
+
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("failed to build http client: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_error(config: HttpConfig) -> String {
+        build(&config).unwrap_err()
+    }
+
+    #[test]
+    fn build_default_client() {
+        assert!(build(&HttpConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn build_with_invalid_proxy() {
+        let error = build_error(HttpConfig {
+            proxy: Some("http://proxy example:3128".into()),
+            ..HttpConfig::default()
+        });
+
+        assert!(error.starts_with("invalid proxy"), "{}", error);
+    }
+
+    #[test]
+    fn build_with_invalid_ca_bundle() {
+        let dir =
+            std::env::temp_dir().join(format!("claude-tray-test-{}-http", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let ca_bundle = |name: &str| HttpConfig {
+            ca_bundle: Some(dir.join(name)),
+            ..HttpConfig::default()
+        };
+
+        let error = build_error(ca_bundle("missing.pem"));
+        assert!(error.starts_with("failed to read"), "{}", error);
+
+        fs::write(dir.join("empty.pem"), "not a certificate").unwrap();
+        let error = build_error(ca_bundle("empty.pem"));
+        assert!(error.starts_with("no certificates found"), "{}", error);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
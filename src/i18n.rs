@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::claude::UsageWindow;
+
+// Languages available for the tray menu and the notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Language {
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "es")]
+    Spanish,
+}
+
+// Texts shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+    Login,
+    AddAccount,
+    SwitchAccount,
+    Logout,
+    OpenClaude,
+    Exit,
+    LoadingUsage,
+    NotLoggedIn,
+    ExtraUsage,
+    Credits,
+    CreditsUsed,
+    ResetsIn,
+    ResettingNow,
+    UsageAbove,
+    UsageAt,
+    LimitReset,
+    AvailableAgain,
+    LoggedOut,
+    AuthLost,
+    RunningOut,
+    OnPaceToRunOut,
+    Resets,
+    History,
+    LastDay,
+    LastWeek,
+    DailyPeaks,
+    NoHistory,
+    ExportHistory,
+    HistoryExported,
+    ExportFailed,
+    RetryingAt,
+    Offline,
+    StaleValuesFrom,
+    RateLimitedUntil,
+    NoConnection,
+    LastUpdated,
+    RefreshNow,
+    ClaudeCodeAccount,
+    WaitingForClaudeCode,
+}
+
+impl Language {
+    // Language of the user session (LC_ALL, LC_MESSAGES or LANG),
+    // English when it is not supported.
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+            .unwrap_or_default();
+
+        if locale.starts_with("es") {
+            Self::Spanish
+        } else {
+            Self::English
+        }
+    }
+
+    pub fn text(self, text: Text) -> &'static str {
+        match (self, text) {
+            (Self::English, Text::Login) => "Log in",
+            (Self::English, Text::AddAccount) => "Add account",
+            (Self::English, Text::SwitchAccount) => "Switch account",
+            (Self::English, Text::Logout) => "Logout",
+            (Self::English, Text::OpenClaude) => "Open Claude",
+            (Self::English, Text::Exit) => "Exit",
+            (Self::English, Text::LoadingUsage) => "Loading usage...",
+            (Self::English, Text::NotLoggedIn) => "not logged in",
+            (Self::English, Text::ExtraUsage) => "Extra usage",
+            (Self::English, Text::Credits) => "credits",
+            (Self::English, Text::CreditsUsed) => "credits used",
+            (Self::English, Text::ResetsIn) => "resets in",
+            (Self::English, Text::ResettingNow) => "resetting now",
+            (Self::English, Text::UsageAbove) => "Claude usage above",
+            (Self::English, Text::UsageAt) => "is at",
+            (Self::English, Text::LimitReset) => "Claude usage limit reset",
+            (Self::English, Text::AvailableAgain) => "is available again",
+            (Self::English, Text::LoggedOut) => "Claude Tray logged out",
+            (Self::English, Text::AuthLost) => {
+                "authentication lost. Log in again from the tray menu."
+            }
+            (Self::English, Text::RunningOut) => "Claude usage running out",
+            (Self::English, Text::OnPaceToRunOut) => "on pace to run out at",
+            (Self::English, Text::Resets) => "resets",
+            (Self::English, Text::History) => "History",
+            (Self::English, Text::LastDay) => "Current session, last 24 hours",
+            (Self::English, Text::LastWeek) => "All models, last 7 days",
+            (Self::English, Text::DailyPeaks) => "Daily peaks",
+            (Self::English, Text::NoHistory) => "No usage recorded yet",
+            (Self::English, Text::ExportHistory) => "Export to CSV",
+            (Self::English, Text::HistoryExported) => "Usage history exported",
+            (Self::English, Text::ExportFailed) => "Usage history export failed",
+            (Self::English, Text::RetryingAt) => "Offline, retrying at",
+            (Self::English, Text::Offline) => "Offline, retrying at the next poll",
+            (Self::English, Text::StaleValuesFrom) => "Values from",
+            (Self::English, Text::RateLimitedUntil) => "Rate limited, next poll at",
+            (Self::English, Text::NoConnection) => "No network connection, polls paused",
+            (Self::English, Text::LastUpdated) => "Last updated",
+            (Self::English, Text::RefreshNow) => "Refresh now",
+            (Self::English, Text::ClaudeCodeAccount) => "Shared with Claude Code",
+            (Self::English, Text::WaitingForClaudeCode) => {
+                "Login expired, waiting for Claude Code to renew it"
+            }
+
+            (Self::Spanish, Text::Login) => "Iniciar sesión",
+            (Self::Spanish, Text::AddAccount) => "Añadir cuenta",
+            (Self::Spanish, Text::SwitchAccount) => "Cambiar de cuenta",
+            (Self::Spanish, Text::Logout) => "Cerrar sesión",
+            (Self::Spanish, Text::OpenClaude) => "Abrir Claude",
+            (Self::Spanish, Text::Exit) => "Salir",
+            (Self::Spanish, Text::LoadingUsage) => "Cargando uso...",
+            (Self::Spanish, Text::NotLoggedIn) => "sin sesión",
+            (Self::Spanish, Text::ExtraUsage) => "Uso adicional",
+            (Self::Spanish, Text::Credits) => "créditos",
+            (Self::Spanish, Text::CreditsUsed) => "créditos usados",
+            (Self::Spanish, Text::ResetsIn) => "se restablece en",
+            (Self::Spanish, Text::ResettingNow) => "restableciéndose",
+            (Self::Spanish, Text::UsageAbove) => "Uso de Claude por encima del",
+            (Self::Spanish, Text::UsageAt) => "está al",
+            (Self::Spanish, Text::LimitReset) => "Límite de uso de Claude restablecido",
+            (Self::Spanish, Text::AvailableAgain) => "está disponible de nuevo",
+            (Self::Spanish, Text::LoggedOut) => "Claude Tray cerró la sesión",
+            (Self::Spanish, Text::AuthLost) => {
+                "se perdió la autenticación. Inicia sesión de nuevo desde el menú."
+            }
+            (Self::Spanish, Text::RunningOut) => "El uso de Claude se está agotando",
+            (Self::Spanish, Text::OnPaceToRunOut) => "a este ritmo se agota a las",
+            (Self::Spanish, Text::Resets) => "se restablece a las",
+            (Self::Spanish, Text::History) => "Historial",
+            (Self::Spanish, Text::LastDay) => "Sesión actual, últimas 24 horas",
+            (Self::Spanish, Text::LastWeek) => "Todos los modelos, últimos 7 días",
+            (Self::Spanish, Text::DailyPeaks) => "Picos diarios",
+            (Self::Spanish, Text::NoHistory) => "Aún no hay uso registrado",
+            (Self::Spanish, Text::ExportHistory) => "Exportar a CSV",
+            (Self::Spanish, Text::HistoryExported) => "Historial de uso exportado",
+            (Self::Spanish, Text::ExportFailed) => "No se pudo exportar el historial de uso",
+            (Self::Spanish, Text::RetryingAt) => "Sin conexión, reintentando a las",
+            (Self::Spanish, Text::Offline) => "Sin conexión, se reintentará en la próxima consulta",
+            (Self::Spanish, Text::StaleValuesFrom) => "Valores de",
+            (Self::Spanish, Text::RateLimitedUntil) => {
+                "Límite de peticiones, próxima consulta a las"
+            }
+            (Self::Spanish, Text::NoConnection) => "Sin conexión de red, consultas en pausa",
+            (Self::Spanish, Text::LastUpdated) => "Última actualización",
+            (Self::Spanish, Text::RefreshNow) => "Actualizar ahora",
+            (Self::Spanish, Text::ClaudeCodeAccount) => "Compartida con Claude Code",
+            (Self::Spanish, Text::WaitingForClaudeCode) => {
+                "Sesión caducada, esperando a que Claude Code la renueve"
+            }
+        }
+    }
+
+    // Title of the group of usage periods of a window
+    pub fn window_title(self, window: UsageWindow) -> &'static str {
+        match (self, window) {
+            (Self::English, UsageWindow::FiveHour) => "Plan usage limits",
+            (Self::English, UsageWindow::SevenDay) => "Weekly usage limits",
+            (Self::English, UsageWindow::Other) => "Other usage limits",
+            (Self::Spanish, UsageWindow::FiveHour) => "Límites de uso del plan",
+            (Self::Spanish, UsageWindow::SevenDay) => "Límites de uso semanales",
+            (Self::Spanish, UsageWindow::Other) => "Otros límites de uso",
+        }
+    }
+
+    // Time passed since an event (e.g. "12m ago")
+    pub fn ago(self, age: &str) -> String {
+        match self {
+            Self::English => format!("{} ago", age),
+            Self::Spanish => format!("hace {}", age),
+        }
+    }
+
+    // Name of a known usage period, by its field name. The periods unknown
+    // by this version keep the name built from their field name.
+    pub fn period_label(self, key: &str, default_label: &str) -> String {
+        let label = match (self, key) {
+            (Self::Spanish, "five_hour") => "Sesión actual",
+            (Self::Spanish, "seven_day") => "Todos los modelos",
+            (Self::Spanish, "seven_day_oauth_apps") => "Apps OAuth",
+            _ => default_label,
+        };
+
+        label.into()
+    }
+}
@@ -0,0 +1,268 @@
+use image::{Rgba, RgbaImage, imageops};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::PI,
+    sync::{LazyLock, Mutex},
+};
+
+// Sizes rendered for the tray, so HiDPI panels don't need to scale the icon.
+pub const ICON_SIZES: [u32; 6] = [16, 22, 24, 32, 48, 64];
+
+const TRACK_COLOR: Rgba<u8> = Rgba([128, 128, 128, 96]);
+const NORMAL_COLOR: Rgba<u8> = Rgba([76, 175, 80, 255]);
+const WARNING_COLOR: Rgba<u8> = Rgba([255, 179, 0, 255]);
+const CRITICAL_COLOR: Rgba<u8> = Rgba([229, 57, 53, 255]);
+// Rings of usage that could not be refreshed
+const STALE_COLOR: Rgba<u8> = Rgba([158, 158, 158, 255]);
+
+// Claude logo drawn in the center of the icon.
+static CLAUDE_LOGO: LazyLock<RgbaImage> = LazyLock::new(|| {
+    image::load_from_memory_with_format(
+        include_bytes!("./assets/claude-icon.png"),
+        image::ImageFormat::Png,
+    )
+    .expect("valid image")
+    .into_rgba8()
+});
+
+// Utilization percentages where the progress rings change their color.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsageThresholds {
+    pub warning: f32,
+    pub critical: f32,
+}
+
+impl Default for UsageThresholds {
+    fn default() -> Self {
+        Self {
+            warning: 50.0,
+            critical: 80.0,
+        }
+    }
+}
+
+impl UsageThresholds {
+    fn color(&self, utilization: f32) -> Rgba<u8> {
+        if utilization >= self.critical {
+            CRITICAL_COLOR
+        } else if utilization >= self.warning {
+            WARNING_COLOR
+        } else {
+            NORMAL_COLOR
+        }
+    }
+}
+
+// Utilization shown by the progress rings of the icon.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IconUsage {
+    pub five_hour: f32,
+    pub seven_day: f32,
+    // The last polls failed, so the utilization may be outdated
+    pub is_stale: bool,
+}
+
+// Renders the tray icon in every size. The outer ring shows the five-hour
+// utilization and the inner one the seven-day utilization. Without usage,
+// only the Claude logo is rendered. Stale usage is rendered in grey.
+fn render_icons(usage: Option<IconUsage>, thresholds: &UsageThresholds) -> Vec<ksni::Icon> {
+    ICON_SIZES
+        .iter()
+        .map(|&size| {
+            let image = match usage {
+                Some(usage) => render_usage(size, usage, thresholds),
+                None => scale_logo(size),
+            };
+
+            to_ksni_icon(image)
+        })
+        .collect()
+}
+
+// Icons rendered for a usage and some thresholds.
+#[derive(Debug)]
+struct RenderedIcons {
+    usage: Option<IconUsage>,
+    thresholds: UsageThresholds,
+    icons: Vec<ksni::Icon>,
+}
+
+// Icons rendered for the last usage and thresholds. The tray asks for the icon
+// after every update, so they are only rendered again when the rings change.
+#[derive(Debug, Default)]
+pub struct IconCache {
+    rendered: Mutex<Option<RenderedIcons>>,
+}
+
+impl IconCache {
+    pub fn icons(&self, usage: Option<IconUsage>, thresholds: &UsageThresholds) -> Vec<ksni::Icon> {
+        let mut rendered = self.rendered.lock().expect("icon cache lock poisoned");
+
+        match &*rendered {
+            Some(rendered) if rendered.usage == usage && rendered.thresholds == *thresholds => {
+                rendered.icons.clone()
+            }
+            _ => {
+                let icons = render_icons(usage, thresholds);
+                *rendered = Some(RenderedIcons {
+                    usage,
+                    thresholds: *thresholds,
+                    icons: icons.clone(),
+                });
+                icons
+            }
+        }
+    }
+}
+
+fn scale_logo(size: u32) -> RgbaImage {
+    imageops::resize(&*CLAUDE_LOGO, size, size, imageops::FilterType::Triangle)
+}
+
+fn render_usage(size: u32, usage: IconUsage, thresholds: &UsageThresholds) -> RgbaImage {
+    let thickness = (size as f32 / 8.0).max(2.0);
+    let gap = (size as f32 / 32.0).max(1.0);
+
+    let outer_radius = size as f32 / 2.0;
+    let inner_radius = outer_radius - thickness - gap;
+    let logo_radius = inner_radius - thickness - gap;
+
+    // The logo is shrunk to fit inside the rings
+    let logo_size = ((logo_radius * 2.0) as u32).max(1);
+    let logo = scale_logo(logo_size);
+
+    let mut image = RgbaImage::new(size, size);
+    let offset = ((size - logo_size) / 2) as i64;
+    imageops::overlay(&mut image, &logo, offset, offset);
+
+    let color = |utilization| {
+        if usage.is_stale {
+            STALE_COLOR
+        } else {
+            thresholds.color(utilization)
+        }
+    };
+
+    draw_ring(
+        &mut image,
+        outer_radius,
+        thickness,
+        usage.five_hour,
+        color(usage.five_hour),
+    );
+    draw_ring(
+        &mut image,
+        inner_radius,
+        thickness,
+        usage.seven_day,
+        color(usage.seven_day),
+    );
+
+    image
+}
+
+// Draws a progress ring centered in the image, filled clockwise
+// from the top according to the utilization.
+fn draw_ring(
+    image: &mut RgbaImage,
+    radius: f32,
+    thickness: f32,
+    utilization: f32,
+    color: Rgba<u8>,
+) {
+    let center = image.width() as f32 / 2.0;
+    let progress = (utilization / 100.0).clamp(0.0, 1.0);
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - center;
+        let dy = y as f32 + 0.5 - center;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        // Partial coverage on the edges smooths the ring
+        let coverage = (radius - distance + 0.5).clamp(0.0, 1.0)
+            * (distance - (radius - thickness) + 0.5).clamp(0.0, 1.0);
+
+        if coverage == 0.0 {
+            continue;
+        }
+
+        let angle = dx.atan2(-dy).rem_euclid(2.0 * PI) / (2.0 * PI);
+        let ring_color = if angle < progress { color } else { TRACK_COLOR };
+
+        blend(pixel, ring_color, coverage);
+    }
+}
+
+// Paints a color over a pixel using alpha compositing.
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, coverage: f32) {
+    let source_alpha = color[3] as f32 / 255.0 * coverage;
+    let destination_alpha = pixel[3] as f32 / 255.0;
+    let alpha = source_alpha + destination_alpha * (1.0 - source_alpha);
+
+    if alpha == 0.0 {
+        return;
+    }
+
+    for channel in 0..3 {
+        let value = (color[channel] as f32 * source_alpha
+            + pixel[channel] as f32 * destination_alpha * (1.0 - source_alpha))
+            / alpha;
+        pixel[channel] = value.round() as u8;
+    }
+
+    pixel[3] = (alpha * 255.0).round() as u8;
+}
+
+// Converts the image into the ARGB format used by the StatusNotifierItem protocol.
+fn to_ksni_icon(image: RgbaImage) -> ksni::Icon {
+    let (width, height) = image.dimensions();
+    let mut data = image.into_vec();
+
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.rotate_right(1)
+    }
+
+    ksni::Icon {
+        width: width as i32,
+        height: height as i32,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(five_hour: f32) -> Option<IconUsage> {
+        Some(IconUsage {
+            five_hour,
+            seven_day: 10.0,
+            is_stale: false,
+        })
+    }
+
+    fn data(icons: &[ksni::Icon]) -> Vec<&[u8]> {
+        icons.iter().map(|icon| icon.data.as_slice()).collect()
+    }
+
+    #[test]
+    fn icon_cache_renders_again_when_the_usage_changes() {
+        let cache = IconCache::default();
+        let thresholds = UsageThresholds::default();
+
+        let first = cache.icons(usage(20.0), &thresholds);
+        assert_eq!(data(&cache.icons(usage(20.0), &thresholds)), data(&first));
+
+        let second = cache.icons(usage(90.0), &thresholds);
+        assert_ne!(data(&second), data(&first));
+        assert_eq!(data(&second), data(&render_icons(usage(90.0), &thresholds)));
+
+        // The inner ring turns from normal to warning
+        let critical = UsageThresholds {
+            warning: 10.0,
+            critical: 15.0,
+        };
+        assert_ne!(data(&cache.icons(usage(90.0), &critical)), data(&second));
+    }
+}
@@ -1,115 +1,850 @@
+mod bar;
 mod claude;
+mod claude_code;
+mod cli;
+mod config;
+mod credentials;
+mod dbus;
+mod error;
+mod export;
+mod forecast;
+mod history;
+mod http;
+mod i18n;
+mod icon;
+mod metrics;
+mod notifications;
+mod retry;
+mod secret_service;
+mod system;
+mod tracking;
 mod utils;
 
-use image::GenericImageView;
+use clap::Parser;
+use jiff::{SignedDuration, Timestamp};
 use ksni::{Handle, TrayMethods, menu::*};
-use std::{sync::LazyLock, time::Duration};
-use tokio::sync::mpsc;
+use log::LevelFilter;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::sync::{mpsc, watch};
 
-use crate::claude::ClaudeCredentials;
+use crate::bar::{BarFormat, BarOutput};
+use crate::claude::{ClaudeCredentials, ClaudeUsageResponse, CredentialSource, UsageWindow};
+use crate::cli::{Cli, Command};
+use crate::config::Config;
+use crate::dbus::{DbusService, ServiceState};
+use crate::export::ExportFormat;
+use crate::forecast::{RunOut, UsageForecast};
+use crate::history::{HistorySummary, UsageHistory};
+use crate::i18n::{Language, Text};
+use crate::icon::{IconCache, IconUsage};
+use crate::metrics::{AccountMetrics, MetricsExporter, MetricsState};
+use crate::notifications::{DesktopNotifications, Notification, UsageNotifications};
+use crate::tracking::{PollState, start_usage_tracking};
 
-// Loading the icon image that is used in the tray
-static CLAUDE_ICON: LazyLock<ksni::Icon> = LazyLock::new(|| {
-    let img = image::load_from_memory_with_format(
-        include_bytes!("./assets/claude-icon.png"),
-        image::ImageFormat::Png,
-    )
-    .expect("valid image");
+// Minimum time between refreshes requested by the user. Repeated clicks
+// in between are ignored, so they don't flood the API
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
-    let (width, height) = img.dimensions();
-    let mut data = img.into_rgba8().into_vec();
+// Time between attempts to watch the Claude Code directory
+// while it doesn't exist (e.g. before Claude Code is installed)
+const CLAUDE_CODE_WATCH_RETRY_INTERVAL: Duration = Duration::from_mins(1);
 
-    for pixel in data.chunks_exact_mut(4) {
-        pixel.rotate_right(1)
+// Whether a refresh requested by the user at `now` has to be ignored, since
+// the previous one was less than `MIN_REFRESH_INTERVAL` before
+fn is_refresh_too_soon(
+    last_refresh: Option<tokio::time::Instant>,
+    now: tokio::time::Instant,
+) -> bool {
+    last_refresh.is_some_and(|last_refresh| now.duration_since(last_refresh) < MIN_REFRESH_INTERVAL)
+}
+
+enum TrayMessage {
+    Login,
+    Refresh,
+    // Messages that refer to an account contain its identifier
+    Logout(String),
+    SwitchAccount(String),
+    // A login started by Login or SwitchAccount finished, with the logged
+    // credentials if it succeeded and the account it replaces, if any
+    LoginFinished(Option<ClaudeCredentials>, Option<String>),
+    ReloadCredentials,
+    ReloadConfig,
+    ExportHistory,
+    StopUsageTracking(String),
+    // The system is going to sleep (true) or resumed (false)
+    Sleep(bool),
+    // The network connection was lost (false) or recovered (true)
+    Connectivity(bool),
+}
+
+// Usage information of a logged account
+#[derive(Debug)]
+struct TrayAccount {
+    // Access token for authentication
+    credentials: ClaudeCredentials,
+    // Last usage received from the API and when it was received. It is
+    // kept when the next polls fail, until the account is logged out
+    usage: Option<ClaudeUsageResponse>,
+    updated_at: Option<Timestamp>,
+    poll_state: PollState,
+    // Consumption rate of the usage between polls
+    forecast: UsageForecast,
+    // Usage recorded in the history during the last week
+    history: HistorySummary,
+}
+
+impl TrayAccount {
+    fn new(credentials: ClaudeCredentials) -> Self {
+        Self {
+            credentials,
+            usage: None,
+            updated_at: None,
+            poll_state: PollState::default(),
+            forecast: UsageForecast::default(),
+            history: HistorySummary::default(),
+        }
     }
 
-    ksni::Icon {
-        width: width as i32,
-        height: height as i32,
-        data,
+    fn id(&self) -> String {
+        self.credentials.id()
     }
-});
 
-enum TrayMessage {
-    Login,
-    StartUsageTracking,
-    StopUsageTracking,
+    // Highest utilization between the usage periods of a window
+    fn window_utilization(&self, window: UsageWindow) -> f32 {
+        self.usage
+            .as_ref()
+            .map(|usage| usage.utilization(window))
+            .unwrap_or_default()
+    }
+
+    // Highest utilization between all the usage periods
+    fn utilization(&self) -> f32 {
+        self.window_utilization(UsageWindow::FiveHour)
+            .max(self.window_utilization(UsageWindow::SevenDay))
+    }
+
+    // Whether the last polls failed, so the usage shown may be outdated
+    fn is_stale(&self) -> bool {
+        self.poll_state != PollState::Online
+    }
+
+    // Connection state and age of the usage shown, when the last polls failed
+    // (e.g. "Offline, retrying at 14:30:05 (2/6)\nValues from 12m ago")
+    fn poll_state_label(&self, language: Language) -> Option<String> {
+        let state = match self.poll_state {
+            PollState::Online => return None,
+            PollState::Retrying {
+                attempt,
+                max_attempts,
+                retry_at,
+            } => format!(
+                "{} {} ({}/{})",
+                language.text(Text::RetryingAt),
+                utils::format_local_clock(retry_at),
+                attempt,
+                max_attempts
+            ),
+            PollState::Offline => language.text(Text::Offline).into(),
+            PollState::WaitingForClaudeCode => language.text(Text::WaitingForClaudeCode).into(),
+            PollState::RateLimited { until } => format!(
+                "{} {}",
+                language.text(Text::RateLimitedUntil),
+                utils::format_local_clock(until)
+            ),
+        };
+
+        Some(match self.updated_at {
+            Some(updated_at) => format!(
+                "{}\n{} {}",
+                state,
+                language.text(Text::StaleValuesFrom),
+                language.ago(&utils::format_age(updated_at))
+            ),
+            None => state,
+        })
+    }
 }
 
 // Tray variables to handle authentication and usage tracking
 #[derive(Debug)]
 struct AppTray {
-    // Visible items status
-    is_login_visible: bool,
-    is_usage_visible: bool,
-    // Access token for authentication
-    access_token: Option<ClaudeCredentials>,
-    // Variables to track usage
-    five_hour_usage: f32,
-    seven_day_usage: f32,
+    // Logged accounts, each one tracked by its own task
+    accounts: Vec<TrayAccount>,
+    // User configuration, replaced every time the file changes
+    config: Config,
+    // Time between usage polls, watched by the tracking tasks
+    poll_interval: watch::Sender<Duration>,
+    // Marked every time the usage of every account has to be polled right away
+    refresh: watch::Sender<()>,
+    // Whether the polls are paused, watched by the tracking tasks. They are
+    // paused while the system sleeps or has no network connection
+    paused: watch::Sender<bool>,
+    is_asleep: bool,
+    is_connected: bool,
+    // Whether a login is waiting for the browser authorization.
+    // Other logins are rejected until it finishes
+    is_logging_in: bool,
+    // Thresholds already notified for every account, and the sender of the
+    // notifications when a notifications service is available
+    usage_notifications: UsageNotifications,
+    desktop_notifications: Option<DesktopNotifications>,
+    // Service exposing the usage in the session bus, when its name is available
+    dbus_service: Option<DbusService>,
+    // Prometheus endpoint, when it is enabled, and the failed
+    // usage requests of every account it exports
+    metrics: Option<MetricsExporter>,
+    poll_errors: BTreeMap<String, u64>,
+    // Database recording every usage poll, when the history is enabled
+    history: Option<UsageHistory>,
+    // Icons of the current usage, rendered again when it changes
+    icons: IconCache,
     // Channel to communicate tray actions with actions that
     // need to be performed asynchronously
     notifier: mpsc::Sender<TrayMessage>,
 }
 
+// Handle to the tray state shared with the tracking tasks. The state is shown
+// by the tray icon, or printed as status bar lines in bar mode.
+#[derive(Clone)]
+enum TrayHandle {
+    Tray(Handle<AppTray>),
+    Bar(BarOutput),
+}
+
+impl TrayHandle {
+    // Runs `f` over the tray state and refreshes what is shown. It returns
+    // `None` if the tray service has been shutdown
+    async fn update<R>(&self, f: impl FnOnce(&mut AppTray) -> R) -> Option<R> {
+        match self {
+            Self::Tray(handle) => handle.update(f).await,
+            Self::Bar(output) => Some(output.update(f)),
+        }
+    }
+}
+
+impl AppTray {
+    fn account_mut(&mut self, account_id: &str) -> Option<&mut TrayAccount> {
+        self.accounts.iter_mut().find(|a| a.id() == account_id)
+    }
+
+    // Adds an account to the tray, replacing the one with the same identifier
+    fn upsert_account(&mut self, credentials: ClaudeCredentials) {
+        match self.account_mut(&credentials.id()) {
+            Some(account) => account.credentials = credentials,
+            None => self.accounts.push(TrayAccount::new(credentials)),
+        }
+    }
+
+    fn language(&self) -> Language {
+        self.config.language()
+    }
+
+    // Applies a new configuration. The tracking tasks reschedule their
+    // polls when the interval changes
+    fn apply_config(&mut self, config: Config) {
+        log::set_max_level(config.log_level);
+
+        self.usage_notifications.configure(
+            config.notifications.thresholds.clone(),
+            config.notifications.hysteresis,
+            config.language(),
+        );
+        self.poll_interval.send_if_modified(|poll_interval| {
+            let is_modified = *poll_interval != config.poll_interval();
+            *poll_interval = config.poll_interval();
+            is_modified
+        });
+
+        self.config = config;
+    }
+
+    // Pauses or resumes the polls after the system sleeps or the network
+    // connection changes. The usage is outdated when they are resumed, so
+    // every account is polled right away
+    fn update_paused(&mut self) {
+        let is_paused = self.is_asleep || !self.is_connected;
+        let was_paused = self.paused.send_replace(is_paused);
+
+        if was_paused && !is_paused {
+            log::info!("resuming usage polls");
+            self.refresh.send_replace(());
+        } else if !was_paused && is_paused {
+            log::info!("pausing usage polls");
+        }
+    }
+
+    // Sender of the notifications, if they are enabled and a
+    // notifications service is available
+    fn desktop_notifications(&self) -> Option<DesktopNotifications> {
+        self.desktop_notifications
+            .clone()
+            .filter(|_| self.config.notifications.enabled)
+    }
+
+    // Removes an account and its state, so the metrics stop exporting it
+    fn remove_account(&mut self, account_id: &str) -> Option<TrayAccount> {
+        self.usage_notifications.forget(account_id);
+        self.poll_errors.remove(account_id);
+
+        let position = self.accounts.iter().position(|a| a.id() == account_id)?;
+        Some(self.accounts.remove(position))
+    }
+
+    // Removes the accounts loaded from Claude Code that are not its current
+    // account anymore, after it logged out or switched accounts
+    fn remove_stale_claude_code_accounts(&mut self, account_id: Option<&str>) -> Vec<String> {
+        let stale: Vec<String> = self
+            .accounts
+            .iter()
+            .filter(|account| {
+                account.credentials.source == CredentialSource::ClaudeCode
+                    && Some(account.id().as_str()) != account_id
+            })
+            .map(TrayAccount::id)
+            .collect();
+
+        for account_id in &stale {
+            self.remove_account(account_id);
+        }
+
+        stale
+    }
+
+    // Highest utilization between all the accounts, so the tray
+    // reflects the account that is closer to its limits
+    fn worst_utilization(&self) -> f32 {
+        self.accounts
+            .iter()
+            .map(TrayAccount::utilization)
+            .fold(0.0, f32::max)
+    }
+
+    // Whether the usage of any account could not be refreshed
+    fn is_stale(&self) -> bool {
+        !self.is_connected || self.accounts.iter().any(TrayAccount::is_stale)
+    }
+
+    // Whether the API asked any account to back off
+    fn is_rate_limited(&self) -> bool {
+        self.accounts
+            .iter()
+            .any(|account| matches!(account.poll_state, PollState::RateLimited { .. }))
+    }
+
+    // Usage shown in the icon. Each period shows the
+    // highest utilization between all the accounts
+    fn icon_usage(&self) -> Option<IconUsage> {
+        if self.accounts.is_empty() {
+            return None;
+        }
+
+        let window_utilization = |window| {
+            self.accounts
+                .iter()
+                .map(|a| a.window_utilization(window))
+                .fold(0.0, f32::max)
+        };
+
+        Some(IconUsage {
+            five_hour: window_utilization(UsageWindow::FiveHour),
+            seven_day: window_utilization(UsageWindow::SevenDay),
+            is_stale: self.is_stale(),
+        })
+    }
+
+    // Values exported through the D-Bus service
+    fn service_state(&self) -> ServiceState {
+        let mut usage = Vec::new();
+
+        for account in &self.accounts {
+            let Some(account_usage) = &account.usage else {
+                continue;
+            };
+
+            usage.extend(account_usage.buckets().into_iter().map(|bucket| {
+                (
+                    account.id(),
+                    bucket.key,
+                    bucket.label,
+                    bucket.period.utilization as f64,
+                    bucket
+                        .period
+                        .resets_at
+                        .map(|resets_at| resets_at.as_second())
+                        .unwrap_or_default(),
+                )
+            }));
+        }
+
+        ServiceState {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| (account.id(), account.credentials.label()))
+                .collect(),
+            usage,
+            utilization: self.worst_utilization() as f64,
+            last_updated: self
+                .accounts
+                .iter()
+                .filter_map(|account| account.updated_at)
+                .max()
+                .map(|updated_at| updated_at.as_second())
+                .unwrap_or_default(),
+            is_logging_in: self.is_logging_in,
+            claude_code_account: self
+                .accounts
+                .iter()
+                .find(|account| account.credentials.source == CredentialSource::ClaudeCode)
+                .map(TrayAccount::id),
+        }
+    }
+
+    // Values exported by the metrics endpoint
+    fn metrics_state(&self) -> MetricsState {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|account| AccountMetrics {
+                account_id: account.id(),
+                email: account
+                    .credentials
+                    .account
+                    .as_ref()
+                    .map(|info| info.email_address.clone())
+                    .unwrap_or_default(),
+                periods: account
+                    .usage
+                    .iter()
+                    .flat_map(|usage| usage.buckets())
+                    .map(|bucket| {
+                        (
+                            bucket.key,
+                            bucket.period.utilization,
+                            bucket.period.resets_at,
+                        )
+                    })
+                    .collect(),
+                extra_usage: account
+                    .usage
+                    .as_ref()
+                    .map(|usage| usage.extra_usage.clone())
+                    .unwrap_or_default(),
+                last_poll: account.updated_at,
+            })
+            .collect();
+
+        MetricsState {
+            accounts,
+            poll_errors: self.poll_errors.clone(),
+        }
+    }
+
+    fn send(&self, message: TrayMessage) {
+        let _ = self
+            .notifier
+            .try_send(message)
+            .map_err(|e| log::error!("{}", e));
+    }
+
+    // Submenu with the usage and options of an account
+    fn account_menu(&self, account: &TrayAccount) -> ksni::MenuItem<Self> {
+        let switch_id = account.id();
+        let logout_id = account.id();
+
+        let language = self.language();
+        let mut submenu: Vec<ksni::MenuItem<Self>> = account
+            .poll_state_label(language)
+            .map(|label| {
+                StandardItem {
+                    label,
+                    icon_name: "network-offline".into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into()
+            })
+            .into_iter()
+            .collect();
+        submenu.extend(usage_menu(
+            account.usage.as_ref(),
+            &account.forecast,
+            language,
+        ));
+
+        submenu.push(MenuItem::Separator);
+
+        // Claude Code keeps its own credentials, so its account would be loaded
+        // again from its file. It has to be logged out from Claude Code
+        if account.credentials.source == CredentialSource::ClaudeCode {
+            submenu.push(
+                StandardItem {
+                    label: language.text(Text::ClaudeCodeAccount).into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        } else {
+            submenu.extend(Self::account_options(switch_id, logout_id, language));
+        }
+
+        SubMenu {
+            label: account.credentials.label(),
+            submenu,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    // Options to change the logged account
+    fn account_options(
+        switch_id: String,
+        logout_id: String,
+        language: Language,
+    ) -> [ksni::MenuItem<Self>; 2] {
+        [
+            StandardItem {
+                label: language.text(Text::SwitchAccount).into(),
+                activate: Box::new(move |this: &mut Self| {
+                    this.send(TrayMessage::SwitchAccount(switch_id.clone()))
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: language.text(Text::Logout).into(),
+                icon_name: "system-log-out".into(),
+                activate: Box::new(move |this: &mut Self| {
+                    this.send(TrayMessage::Logout(logout_id.clone()))
+                }),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+
+    // Submenu with the recent usage of every account, when the history is enabled
+    fn history_menu(&self) -> Option<ksni::MenuItem<Self>> {
+        if self.history.is_none() || self.accounts.is_empty() {
+            return None;
+        }
+
+        let language = self.language();
+        let info_item = |label: String| -> ksni::MenuItem<Self> {
+            StandardItem {
+                label,
+                enabled: false,
+                ..Default::default()
+            }
+            .into()
+        };
+
+        let mut submenu = Vec::new();
+
+        for account in &self.accounts {
+            if !submenu.is_empty() {
+                submenu.push(MenuItem::Separator);
+            }
+
+            // Accounts are only told apart when there are several of them
+            if self.accounts.len() > 1 {
+                submenu.push(info_item(account.credentials.label()));
+            }
+
+            let summary = &account.history;
+
+            if summary.is_empty() {
+                submenu.push(info_item(language.text(Text::NoHistory).into()));
+                continue;
+            }
+
+            submenu.extend([
+                info_item(format!(
+                    "{}\n{}",
+                    language.text(Text::LastDay),
+                    utils::format_sparkline(&summary.day)
+                )),
+                info_item(format!(
+                    "{}\n{}",
+                    language.text(Text::LastWeek),
+                    utils::format_sparkline(&summary.week)
+                )),
+                info_item(language.text(Text::DailyPeaks).into()),
+            ]);
+
+            submenu.extend(
+                summary
+                    .daily_peaks
+                    .iter()
+                    .map(|(date, five_hour, seven_day)| {
+                        info_item(format!(
+                            "{}: 5h {:.0}% · 7d {:.0}%",
+                            date.strftime("%a %d"),
+                            five_hour,
+                            seven_day
+                        ))
+                    }),
+            );
+        }
+
+        submenu.extend([
+            MenuItem::Separator,
+            StandardItem {
+                label: language.text(Text::ExportHistory).into(),
+                icon_name: "document-save-as".into(),
+                activate: Box::new(|this: &mut Self| this.send(TrayMessage::ExportHistory)),
+                ..Default::default()
+            }
+            .into(),
+        ]);
+
+        Some(
+            SubMenu {
+                label: language.text(Text::History).into(),
+                icon_name: "document-open-recent".into(),
+                submenu,
+                ..Default::default()
+            }
+            .into(),
+        )
+    }
+}
+
+// Disabled items showing every usage period, grouped by their window,
+// and the extra usage credits when they are enabled
+fn usage_menu(
+    usage: Option<&ClaudeUsageResponse>,
+    forecast: &UsageForecast,
+    language: Language,
+) -> Vec<ksni::MenuItem<AppTray>> {
+    let info_item = |label: String| -> ksni::MenuItem<AppTray> {
+        StandardItem {
+            label,
+            enabled: false,
+            ..Default::default()
+        }
+        .into()
+    };
+
+    let Some(usage) = usage else {
+        return vec![info_item(language.text(Text::LoadingUsage).into())];
+    };
+
+    let buckets = usage.buckets();
+    let mut items = Vec::new();
+
+    for window in [
+        UsageWindow::FiveHour,
+        UsageWindow::SevenDay,
+        UsageWindow::Other,
+    ] {
+        let window_buckets: Vec<_> = buckets.iter().filter(|b| b.window == window).collect();
+
+        if window_buckets.is_empty() {
+            continue;
+        }
+
+        items.push(info_item(language.window_title(window).into()));
+
+        for bucket in window_buckets {
+            items.push(info_item(usage_label(
+                &language.period_label(&bucket.key, &bucket.label),
+                bucket.period.utilization,
+                bucket.period.resets_at,
+                forecast.run_out(&bucket.key),
+                language,
+            )));
+        }
+    }
+
+    if let Some(credits) = utils::format_extra_usage(&usage.extra_usage, language) {
+        items.push(info_item(format!(
+            "{}\n{}",
+            language.text(Text::ExtraUsage),
+            credits
+        )));
+    }
+
+    items
+}
+
+// Label of a usage period with its utilization, the time left until it
+// resets and when it runs out at the current pace
+fn usage_label(
+    title: &str,
+    utilization: f32,
+    resets_at: Option<Timestamp>,
+    run_out: Option<RunOut>,
+    language: Language,
+) -> String {
+    let label = match resets_at {
+        Some(resets_at) => format!(
+            "{} ({}/100)\n{}",
+            title,
+            utilization,
+            utils::format_reset(resets_at, language)
+        ),
+        None => format!("{} ({}/100)", title, utilization),
+    };
+
+    match run_out {
+        Some(run_out) => format!("{}\n{}", label, utils::format_run_out(&run_out, language)),
+        None => label,
+    }
+}
+
 // Options to show in the tray menu application
 impl ksni::Tray for AppTray {
     // Identifier for the tray
     fn id(&self) -> String {
         env!("CARGO_PKG_NAME").into()
     }
-    // Custom icon for the tray
+    // Left click polls the usage right away
+    fn activate(&mut self, _x: i32, _y: i32) {
+        if !self.accounts.is_empty() {
+            self.send(TrayMessage::Refresh);
+        }
+    }
+    // Custom icon for the tray, rendered with the current usage
     fn icon_pixmap(&self) -> Vec<ksni::Icon> {
-        vec![CLAUDE_ICON.clone()]
+        self.icons.icons(self.icon_usage(), &self.config.icon)
     }
     // Title for the tray
     fn title(&self) -> String {
         "Claude Tray".into()
     }
+    // The tray asks for attention once any account reaches its limits
+    fn status(&self) -> ksni::Status {
+        if self.worst_utilization() >= 100.0 {
+            ksni::Status::NeedsAttention
+        } else {
+            ksni::Status::Active
+        }
+    }
+    // Summary of the usage of every account
+    fn tool_tip(&self) -> ksni::ToolTip {
+        let language = self.language();
+        let description = self
+            .accounts
+            .iter()
+            .map(|account| {
+                let mut lines = vec![account.credentials.label()];
+                lines.extend(account.poll_state_label(language));
+
+                if let Some(usage) = &account.usage {
+                    for bucket in usage.buckets() {
+                        lines.push(utils::format_period(
+                            &language.period_label(&bucket.key, &bucket.label),
+                            bucket.period.utilization,
+                            bucket.period.resets_at,
+                            language,
+                        ));
+                        lines.extend(
+                            account
+                                .forecast
+                                .run_out(&bucket.key)
+                                .map(|run_out| utils::format_run_out(&run_out, language)),
+                        );
+                    }
+
+                    lines.extend(utils::format_extra_usage(&usage.extra_usage, language));
+                }
+
+                lines.join("\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let description = if self.is_connected {
+            description
+        } else {
+            format!("{}\n{}", language.text(Text::NoConnection), description)
+        };
+
+        ksni::ToolTip {
+            title: format!("Claude Tray ({}%)", self.worst_utilization()),
+            description,
+            ..Default::default()
+        }
+    }
     // Menu items for the tray
     fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
-        vec![
-            // Login option in case user is not logged in
-            StandardItem {
-                label: "Iniciar sesión".into(),
-                // For some reason, using Cosmic, this element cannot be hidden
-                visible: self.is_login_visible,
-                activate: Box::new(|this: &mut Self| {
-                    let _ = this
-                        .notifier
-                        .try_send(TrayMessage::Login)
-                        .map_err(|e| log::error!("{}", e));
-                }),
-                ..Default::default()
-            }
-            .into(),
-            StandardItem {
-                label: format!(
-                    "Plan usage limits\nCurrent session ({}/100)",
-                    self.five_hour_usage
-                ),
-                visible: self.is_usage_visible,
-                enabled: false,
-                ..Default::default()
-            }
-            .into(),
+        let language = self.language();
+        let mut menu: Vec<ksni::MenuItem<Self>> = self
+            .accounts
+            .iter()
+            .map(|account| self.account_menu(account))
+            .collect();
+
+        if !self.is_connected {
+            menu.insert(
+                0,
+                StandardItem {
+                    label: language.text(Text::NoConnection).into(),
+                    icon_name: "network-offline".into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        menu.extend(self.history_menu());
+
+        if !self.accounts.is_empty() {
+            // Time of the latest usage poll between all the accounts
+            let last_updated = self
+                .accounts
+                .iter()
+                .filter_map(|account| account.updated_at)
+                .max()
+                .map(utils::format_local_clock)
+                .unwrap_or_else(|| "-".into());
+
+            menu.extend([
+                StandardItem {
+                    label: format!("{} {}", language.text(Text::LastUpdated), last_updated),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+                StandardItem {
+                    label: language.text(Text::RefreshNow).into(),
+                    icon_name: "view-refresh".into(),
+                    activate: Box::new(|this: &mut Self| this.send(TrayMessage::Refresh)),
+                    ..Default::default()
+                }
+                .into(),
+            ]);
+        }
+
+        menu.extend([
+            // Login option to add an account. It is the only
+            // option available when no account is logged in
             StandardItem {
-                label: format!(
-                    "Weekly usage limits\nAll models ({}/100)",
-                    self.seven_day_usage
-                ),
-                visible: self.is_usage_visible,
-                enabled: false,
+                label: if self.accounts.is_empty() {
+                    language.text(Text::Login).into()
+                } else {
+                    language.text(Text::AddAccount).into()
+                },
+                enabled: !self.is_logging_in,
+                activate: Box::new(|this: &mut Self| this.send(TrayMessage::Login)),
                 ..Default::default()
             }
             .into(),
             MenuItem::Separator,
             // Option to open ClaudeIA using the browser
             StandardItem {
-                label: "Open Claude".into(),
-                activate: Box::new(|_| {
-                    webbrowser::open("https://claude.ai/new")
-                        .expect("error opening claude on the browser")
+                label: language.text(Text::OpenClaude).into(),
+                activate: Box::new(|this: &mut Self| {
+                    if let Err(e) = webbrowser::open(&this.config.claude_url) {
+                        log::error!("error opening claude on the browser: {}", e);
+                    }
                 }),
                 ..Default::default()
             }
@@ -117,110 +852,474 @@ impl ksni::Tray for AppTray {
             MenuItem::Separator,
             // Option to exit the application
             StandardItem {
-                label: "Exit".into(),
+                label: language.text(Text::Exit).into(),
                 icon_name: "application-exit".into(),
                 activate: Box::new(|_| std::process::exit(0)),
                 ..Default::default()
             }
             .into(),
-        ]
+        ]);
+
+        menu
     }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    let cli = Cli::parse();
+
+    // Every record reaches the logger, and the configured level is applied
+    // as the global maximum level. Commands other than the tray only log
+    // warnings by default, to keep their output clean
     env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Trace)
+        .filter_level(LevelFilter::Trace)
         .init();
+    log::set_max_level(cli.log_level.unwrap_or(LevelFilter::Warn));
+
+    let config_path = cli.config.clone().or_else(|| {
+        Config::default_path()
+            .map_err(|e| log::error!("{}", e))
+            .ok()
+    });
+    let mut config = Config::load_or_default(config_path.as_deref());
+
+    if let Some(log_level) = cli.log_level {
+        config.log_level = log_level;
+    }
+
+    if let Err(e) = http::configure(&config.http) {
+        log::error!("{}", e);
+    }
+
+    let command = cli.command.unwrap_or(Command::Daemon);
+
+    // The Claude commands fail with a `ClaudeError`, and the export
+    // with a string error. Both are only printed
+    let result: Result<(), Box<dyn std::error::Error>> = match command {
+        Command::Status { json } => cli::status(&config, json).await.map_err(Into::into),
+        Command::Login => cli::login(&config).await.map_err(Into::into),
+        Command::Logout { account } => cli::logout(account.as_deref()).await.map_err(Into::into),
+        Command::Daemon => {
+            run_tray(config_path, config, cli.log_level, None).await;
+            Ok(())
+        }
+        Command::Bar { format } => {
+            run_tray(config_path, config, cli.log_level, Some(format)).await;
+            Ok(())
+        }
+        Command::Export {
+            from,
+            to,
+            format,
+            output,
+        } => cli::export(from, to, format, output.as_deref()).map_err(Into::into),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Runs the tray until it is exited. The log level given in the command
+// line is kept when the configuration is reloaded. With a bar format, the
+// usage is printed for a status bar instead of showing a tray icon.
+async fn run_tray(
+    config_path: Option<PathBuf>,
+    mut config: Config,
+    log_level: Option<LevelFilter>,
+    bar_format: Option<BarFormat>,
+) {
+    log::set_max_level(config.log_level);
 
     let (notifier, mut tray_msgs) = mpsc::channel::<TrayMessage>(1);
+    let login_notifier = notifier.clone();
+
+    // Other desktop components read the usage and control the tray through D-Bus
+    let dbus_service = DbusService::start(notifier.clone())
+        .await
+        .map_err(|e| log::warn!("d-bus service not available: {}", e))
+        .ok();
+
+    let metrics = start_metrics(&config).await;
+
+    // Polls are paused while the system sleeps or is offline
+    if let Err(e) = system::watch(notifier.clone()).await {
+        log::warn!("{}", e);
+    }
+
+    // The configuration is applied again every time its file changes
+    let config_notifier = notifier.clone();
+    let _config_watcher = config_path.as_deref().and_then(|path| {
+        config::watch(path, move || {
+            let _ = config_notifier.blocking_send(TrayMessage::ReloadConfig);
+        })
+        .map_err(|e| log::warn!("{}", e))
+        .ok()
+    });
+
+    // Claude Code credentials are reloaded when it logs in again or refreshes its token
+    let credentials_notifier = notifier.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLAUDE_CODE_WATCH_RETRY_INTERVAL);
+        let mut attempts = 0;
+
+        let _watcher = loop {
+            interval.tick().await;
+            attempts += 1;
+
+            let notifier = credentials_notifier.clone();
+            match claude_code::watch_credentials(move || {
+                let _ = notifier.blocking_send(TrayMessage::ReloadCredentials);
+            }) {
+                Ok(watcher) => break watcher,
+                Err(e) => log::trace!("{}. retrying in {:?}", e, CLAUDE_CODE_WATCH_RETRY_INTERVAL),
+            }
+        };
+
+        // The credentials may have been written before the directory was watched
+        if attempts > 1 {
+            let _ = credentials_notifier
+                .send(TrayMessage::ReloadCredentials)
+                .await;
+        }
+
+        // The watcher stops when it is dropped
+        std::future::pending::<()>().await;
+    });
 
     // Initial tray values before executing
     // updater task to update usage information
     let tray = AppTray {
-        is_login_visible: true,
-        is_usage_visible: false,
-        access_token: None,
-        five_hour_usage: 0.0,
-        seven_day_usage: 0.0,
+        accounts: Vec::new(),
+        poll_interval: watch::Sender::new(config.poll_interval()),
+        refresh: watch::Sender::new(()),
+        paused: watch::Sender::new(false),
+        is_asleep: false,
+        is_connected: true,
+        is_logging_in: false,
+        usage_notifications: UsageNotifications::new(
+            config.notifications.thresholds.clone(),
+            config.notifications.hysteresis,
+            config.language(),
+        ),
+        config: config.clone(),
+        desktop_notifications: DesktopNotifications::connect()
+            .await
+            .map_err(|e| log::warn!("desktop notifications not available: {}", e))
+            .ok(),
+        dbus_service,
+        metrics,
+        poll_errors: BTreeMap::new(),
+        history: open_history(&config),
+        icons: IconCache::default(),
         notifier,
     };
-    let handle = tray
-        .spawn()
-        .await
-        .expect("tray handler error while spawning");
+    let handle = match bar_format {
+        Some(format) => TrayHandle::Bar(BarOutput::new(tray, format)),
+        None => TrayHandle::Tray(
+            tray.spawn()
+                .await
+                .expect("tray handler error while spawning"),
+        ),
+    };
 
-    match claude::get_local_credentials() {
-        Ok(access_token) => {
-            handle
-                .update(|tray: &mut AppTray| {
-                    tray.access_token = Some(access_token);
+    // Refreshes the tray every minute, so the reset countdowns
+    // keep ticking between usage polls
+    let handle_countdown = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_mins(1));
+
+        loop {
+            interval.tick().await;
+            handle_countdown.update(|_: &mut AppTray| {}).await;
+        }
+    });
 
-                    tray.is_login_visible = false;
-                    tray.is_usage_visible = true;
+    // Old usage samples are expired and compacted once a day
+    let handle_history = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_hours(24));
 
-                    log::trace!("credentials loaded from locally source. Hidding login button");
+        loop {
+            interval.tick().await;
 
-                    let _ = tray.notifier.try_send(TrayMessage::StartUsageTracking);
+            let Some((history, history_config)) = handle_history
+                .update(|tray: &mut AppTray| {
+                    tray.history
+                        .clone()
+                        .map(|history| (history, tray.config.history.clone()))
                 })
-                .await;
+                .await
+                .flatten()
+            else {
+                continue;
+            };
+
+            if let Ok(Err(e)) =
+                tokio::task::spawn_blocking(move || history.compact(&history_config)).await
+            {
+                log::error!("{}", e);
+            }
+        }
+    });
+
+    let mut tracking_tasks: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
+    // Time of the last refresh requested by the user
+    let mut last_refresh: Option<tokio::time::Instant> = None;
+
+    match claude::get_local_credentials().await {
+        Ok(accounts) => {
+            log::trace!(
+                "{} accounts loaded from the credentials stores",
+                accounts.len()
+            );
+
+            for credentials in accounts {
+                let account_id = credentials.id();
+
+                handle
+                    .update(|tray: &mut AppTray| tray.upsert_account(credentials))
+                    .await;
+
+                start_usage_tracking(&handle, &mut tracking_tasks, account_id).await;
+            }
         }
         Err(e) => {
             log::error!("{}", e);
         }
     }
 
-    let mut tracking_task: Option<tokio::task::JoinHandle<()>> = None;
-
     loop {
         tokio::select! {
             Some(msg) = tray_msgs.recv() => {
                 match msg {
                     // This code is executed when the login button is clicked
                     TrayMessage::Login => {
-                        let claude_credentials = match claude::open_oauth_login().await {
+                        if start_login(&handle).await {
+                            spawn_login(config.oauth_redirect_port, login_notifier.clone(), None);
+                        }
+                    }
+
+                    // This code is executed when the switch account button of an account is clicked
+                    TrayMessage::SwitchAccount(previous_id) => {
+                        if start_login(&handle).await {
+                            spawn_login(config.oauth_redirect_port, login_notifier.clone(), Some(previous_id));
+                        }
+                    }
+
+                    // This code is executed when the browser authorization of a login finishes
+                    TrayMessage::LoginFinished(credentials, previous_id) => {
+                        handle
+                            .update(|tray: &mut AppTray| tray.is_logging_in = false)
+                            .await;
+
+                        let Some(credentials) = credentials else {
+                            publish_state(&handle).await;
+                            continue;
+                        };
+                        let account_id = credentials.id();
+
+                        // The previous account is replaced by the new one
+                        if let Some(previous_id) = previous_id
+                            && account_id != previous_id
+                        {
+                            logout(&handle, &mut tracking_tasks, &previous_id).await;
+                        }
+
+                        handle
+                            .update(|tray: &mut AppTray| tray.upsert_account(credentials))
+                            .await;
+
+                        start_usage_tracking(&handle, &mut tracking_tasks, account_id).await;
+                    }
+
+                    // This code is executed when a refresh of the usage is requested
+                    TrayMessage::Refresh => {
+                        if is_refresh_too_soon(last_refresh, tokio::time::Instant::now()) {
+                            log::trace!("usage refreshed less than {:?} ago. ignoring refresh", MIN_REFRESH_INTERVAL);
+                            continue;
+                        }
+
+                        log::trace!("refreshing usage of every account");
+                        last_refresh = Some(tokio::time::Instant::now());
+
+                        handle
+                            .update(|tray: &mut AppTray| tray.refresh.send_replace(()))
+                            .await;
+                    }
+
+                    // This code is executed when the logout button of an account is clicked
+                    TrayMessage::Logout(account_id) => {
+                        logout(&handle, &mut tracking_tasks, &account_id).await;
+                    }
+
+                    // This code is executed when the claude code credentials file changes
+                    TrayMessage::ReloadCredentials => {
+                        let credentials = match claude_code::load_credentials() {
                             Ok(credentials) => credentials,
                             Err(e) => {
                                 log::error!("{}", e);
                                 continue;
                             }
                         };
-                        let access_token = match claude::save_credentials_locally(&claude_credentials) {
-                            Ok(credentials) => credentials,
+                        let current_id = credentials.as_ref().map(ClaudeCredentials::id);
+
+                        let stale = handle
+                            .update(|tray: &mut AppTray| {
+                                tray.remove_stale_claude_code_accounts(current_id.as_deref())
+                            })
+                            .await
+                            .unwrap_or_default();
+
+                        if !stale.is_empty() {
+                            for account_id in &stale {
+                                log::info!("{} is no longer the account of claude code. removing it", account_id);
+
+                                if let Some(task) = tracking_tasks.remove(account_id) {
+                                    task.abort();
+                                }
+                            }
+
+                            publish_state(&handle).await;
+                        }
+
+                        let Some(credentials) = credentials else {
+                            continue;
+                        };
+                        let account_id = credentials.id();
+
+                        let is_changed = handle
+                            .update(|tray: &mut AppTray| {
+                                // Accounts also logged in the tray keep their own credentials
+                                let is_changed = match tray.account_mut(&account_id) {
+                                    Some(account) => {
+                                        account.credentials.source == CredentialSource::ClaudeCode
+                                            && account.credentials.access_token != credentials.access_token
+                                    }
+                                    None => true,
+                                };
+
+                                if is_changed {
+                                    tray.upsert_account(credentials);
+                                }
+
+                                is_changed
+                            })
+                            .await
+                            .unwrap_or_default();
+
+                        if is_changed {
+                            log::info!("claude code credentials changed. restarting usage tracking");
+
+                            start_usage_tracking(&handle, &mut tracking_tasks, account_id).await;
+                        }
+                    }
+
+                    // This code is executed when the configuration file changes. An
+                    // invalid configuration is ignored, keeping the previous one
+                    TrayMessage::ReloadConfig => {
+                        let Some(path) = config_path.as_deref() else {
+                            continue;
+                        };
+
+                        let mut new_config = match Config::load(path) {
+                            Ok(new_config) => new_config,
                             Err(e) => {
-                                log::error!("{}", e);
+                                log::error!("{}. keeping the previous configuration", e);
                                 continue;
                             }
                         };
 
+                        if let Some(log_level) = log_level {
+                            new_config.log_level = log_level;
+                        }
+
+                        if new_config == config {
+                            continue;
+                        }
+
+                        log::info!("configuration changed. applying it");
+
+                        // The metrics endpoint is started again on its new address
+                        if new_config.metrics != config.metrics {
+                            let previous = handle
+                                .update(|tray: &mut AppTray| tray.metrics.take())
+                                .await
+                                .flatten();
+
+                            if let Some(previous) = previous {
+                                previous.stop();
+                            }
+
+                            let metrics = start_metrics(&new_config).await;
+                            handle
+                                .update(|tray: &mut AppTray| tray.metrics = metrics)
+                                .await;
+                        }
+
+                        // Requests in progress finish with the previous client. It is also kept
+                        // when the new one cannot be built (e.g. the ca bundle can't be read)
+                        if new_config.http != config.http
+                            && let Err(e) = http::configure(&new_config.http)
+                        {
+                            log::error!("{}. keeping the previous http client", e);
+                        }
+
+                        if new_config.history.enabled != config.history.enabled {
+                            let history = open_history(&new_config);
+                            handle
+                                .update(|tray: &mut AppTray| tray.history = history)
+                                .await;
+                        }
+
+                        config = new_config.clone();
+
                         handle
-                            .update(|tray: &mut AppTray| {
-                                tray.access_token = Some(access_token);
+                            .update(|tray: &mut AppTray| tray.apply_config(new_config))
+                            .await;
 
-                                tray.is_login_visible = false;
-                                tray.is_usage_visible = true;
+                        publish_state(&handle).await;
+                    }
+
+                    // This code is executed when the export option of the history menu is clicked
+                    TrayMessage::ExportHistory => {
+                        export_history(&handle).await;
+                    }
 
-                                let _ = tray.notifier.try_send(TrayMessage::StartUsageTracking)
-                                    .map_err(|e| log::error!("{}", e));
+                    // This code is executed when logind reports a suspend or a resume
+                    TrayMessage::Sleep(is_asleep) => {
+                        log::trace!("system {}", if is_asleep { "going to sleep" } else { "resumed" });
+
+                        handle
+                            .update(|tray: &mut AppTray| {
+                                tray.is_asleep = is_asleep;
+                                tray.update_paused();
                             })
                             .await;
                     }
 
-                    TrayMessage::StartUsageTracking => {
-                        if tracking_task.is_none() {
-                            if let Ok(task) = usage_tracking_task(&handle).await {
-                                tracking_task = Some(task);
-                            } else {
-                                log::error!("failed to start usage tracking");
-                            }
-                        }
+                    // This code is executed when networkmanager reports a connectivity change
+                    TrayMessage::Connectivity(is_connected) => {
+                        log::trace!("network {}", if is_connected { "connected" } else { "disconnected" });
+
+                        handle
+                            .update(|tray: &mut AppTray| {
+                                tray.is_connected = is_connected;
+                                tray.update_paused();
+                            })
+                            .await;
+
+                        publish_state(&handle).await;
                     }
 
-                    TrayMessage::StopUsageTracking => {
-                        log::trace!("stopping usage tracking");
-                        if let Some(task) = tracking_task.take() {
+                    TrayMessage::StopUsageTracking(account_id) => {
+                        log::trace!("stopping usage tracking of {}", account_id);
+                        if let Some(task) = tracking_tasks.remove(&account_id) {
                             task.abort();
                         }
+
+                        publish_state(&handle).await;
                     }
                 }
             }
@@ -228,61 +1327,236 @@ async fn main() {
     }
 }
 
-async fn usage_tracking_task(
-    handle: &Handle<AppTray>,
-) -> Result<tokio::task::JoinHandle<()>, String> {
-    let handle_tracking = handle.clone();
+// Starts the metrics endpoint if it is enabled in the configuration.
+async fn start_metrics(config: &Config) -> Option<MetricsExporter> {
+    if !config.metrics.enabled {
+        return None;
+    }
+
+    MetricsExporter::start(config.metrics.address)
+        .await
+        .map_err(|e| log::error!("{}", e))
+        .ok()
+}
 
-    let Some(credentials) = handle
+// Opens the usage history database if it is enabled in the configuration.
+fn open_history(config: &Config) -> Option<UsageHistory> {
+    if !config.history.enabled {
+        return None;
+    }
+
+    UsageHistory::default_path()
+        .and_then(|path| UsageHistory::open(&path))
+        .map_err(|e| log::error!("{}", e))
+        .ok()
+}
+
+// Exports the whole usage history to a CSV file, notifying where it was written.
+async fn export_history(handle: &TrayHandle) {
+    let Some((history, language, desktop)) = handle
         .update(|tray: &mut AppTray| {
-            tray.access_token
-                .as_ref()
-                .map(|token| token.access_token.clone())
+            tray.history
+                .clone()
+                .map(|history| (history, tray.language(), tray.desktop_notifications()))
         })
         .await
         .flatten()
     else {
-        return Err("no credentials available".into());
+        return;
     };
 
-    let tracking_task = tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_mins(5));
+    let now = Timestamp::now();
+    let result = tokio::task::spawn_blocking(move || {
+        let path = export::default_path(ExportFormat::Csv, now)?;
+        let samples = history.samples(
+            None,
+            Timestamp::UNIX_EPOCH,
+            now + SignedDuration::from_secs(1),
+        )?;
+        export::write_file(&samples, ExportFormat::Csv, &path)?;
 
-        let tracking_result: Result<(), String> = {
-            loop {
-                interval.tick().await;
+        Ok(path)
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("failed to export usage history: {}", e)));
 
-                log::trace!("getting usage data from claude api");
+    let notification = match result {
+        Ok(path) => {
+            log::info!("usage history exported to {:?}", path);
+            Notification::history_exported(&path, language)
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            Notification::export_failed(&e, language)
+        }
+    };
 
-                if let Ok(usage) = claude::get_usage(&credentials).await {
-                    handle_tracking
-                        .update(|tray: &mut AppTray| {
-                            tray.five_hour_usage = usage.five_hour.utilization;
-                            tray.seven_day_usage = usage.seven_day.utilization;
-                        })
-                        .await;
-                } else {
-                    break Err("failed to get usage data".into());
-                }
+    if let Some(desktop) = desktop {
+        desktop.send_all(&[notification]).await;
+    }
+}
+
+// Marks a login as in progress. It returns false, and the login has to be
+// ignored, when another one is still waiting for the browser authorization.
+async fn start_login(handle: &TrayHandle) -> bool {
+    let is_started = handle
+        .update(|tray: &mut AppTray| !std::mem::replace(&mut tray.is_logging_in, true))
+        .await
+        .unwrap_or_default();
+
+    if is_started {
+        publish_state(handle).await;
+    } else {
+        log::warn!("a login is already in progress. ignoring login");
+    }
+
+    is_started
+}
+
+// Runs the login in its own task, so the tray keeps running while the browser
+// authorization is waited for. The result is sent back as `LoginFinished`.
+fn spawn_login(
+    redirect_port: u16,
+    notifier: mpsc::Sender<TrayMessage>,
+    previous_id: Option<String>,
+) {
+    tokio::spawn(async move {
+        let credentials = login(redirect_port).await;
+
+        let _ = notifier
+            .send(TrayMessage::LoginFinished(credentials, previous_id))
+            .await;
+    });
+}
+
+// Runs the OAuth login and saves the credentials of the logged account.
+async fn login(redirect_port: u16) -> Option<ClaudeCredentials> {
+    let claude_credentials = claude::open_oauth_login(redirect_port)
+        .await
+        .map_err(|e| log::error!("{}", e))
+        .ok()?;
+
+    claude::save_credentials_locally(&claude_credentials)
+        .await
+        .map_err(|e| log::error!("{}", e))
+        .ok()
+}
+
+// Stops tracking an account, removes it from the tray and wipes its credentials.
+async fn logout(
+    handle: &TrayHandle,
+    tracking_tasks: &mut HashMap<String, tokio::task::JoinHandle<()>>,
+    account_id: &str,
+) {
+    // The account of Claude Code would be loaded again from its file the next
+    // time it changes. The D-Bus service already rejects it, but a request
+    // can arrive before the service state is updated
+    let is_claude_code = handle
+        .update(|tray: &mut AppTray| {
+            tray.account_mut(account_id)
+                .is_some_and(|account| account.credentials.source == CredentialSource::ClaudeCode)
+        })
+        .await
+        .unwrap_or_default();
+
+    if is_claude_code {
+        log::warn!(
+            "{} is the account of Claude Code. log out from Claude Code instead",
+            account_id
+        );
+        return;
+    }
+
+    log::info!("logging out {}", account_id);
+
+    if let Some(task) = tracking_tasks.remove(account_id) {
+        task.abort();
+    }
+
+    // The tokens are only deleted locally, they are not revoked in the OAuth server
+    handle
+        .update(|tray: &mut AppTray| tray.remove_account(account_id))
+        .await;
+
+    if let Err(e) = claude::delete_local_credentials(account_id).await {
+        log::error!("{}", e);
+    }
+
+    publish_state(handle).await;
+}
+
+// Exports the current values of the tray through the metrics endpoint
+// and the D-Bus service, if they are running.
+async fn publish_state(handle: &TrayHandle) {
+    let Some((service, state)) = handle
+        .update(|tray: &mut AppTray| {
+            if let Some(metrics) = &tray.metrics {
+                metrics.update(tray.metrics_state());
             }
-        };
 
-        if let Err(error) = tracking_result {
-            log::error!("usage tracking failed: {}", error);
+            tray.dbus_service
+                .clone()
+                .map(|service| (service, tray.service_state()))
+        })
+        .await
+        .flatten()
+    else {
+        return;
+    };
 
-            handle_tracking
-                .update(|tray: &mut AppTray| {
-                    tray.is_login_visible = true;
-                    tray.is_usage_visible = false;
+    if let Err(e) = service.update(state).await {
+        log::warn!("{}", e);
+    }
+}
 
-                    tray.five_hour_usage = 0.0;
-                    tray.seven_day_usage = 0.0;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                    let _ = tray.notifier.try_send(TrayMessage::StopUsageTracking);
-                })
-                .await;
+    // Tray in English with the given accounts, without any of the services
+    pub fn tray(accounts: Vec<TrayAccount>) -> AppTray {
+        let config = Config {
+            language: Some(Language::English),
+            ..Config::default()
+        };
+
+        AppTray {
+            accounts,
+            poll_interval: watch::Sender::new(config.poll_interval()),
+            refresh: watch::Sender::new(()),
+            paused: watch::Sender::new(false),
+            is_asleep: false,
+            is_connected: true,
+            is_logging_in: false,
+            usage_notifications: UsageNotifications::new(
+                config.notifications.thresholds.clone(),
+                config.notifications.hysteresis,
+                Language::English,
+            ),
+            config,
+            desktop_notifications: None,
+            dbus_service: None,
+            metrics: None,
+            poll_errors: BTreeMap::new(),
+            history: None,
+            icons: IconCache::default(),
+            notifier: mpsc::channel(1).0,
         }
-    });
+    }
+
+    #[test]
+    fn refreshes_are_ignored_for_a_while() {
+        let last_refresh = tokio::time::Instant::now();
 
-    Ok(tracking_task)
+        assert!(!is_refresh_too_soon(None, last_refresh));
+        assert!(is_refresh_too_soon(Some(last_refresh), last_refresh));
+        assert!(is_refresh_too_soon(
+            Some(last_refresh),
+            last_refresh + MIN_REFRESH_INTERVAL - Duration::from_secs(1)
+        ));
+        assert!(!is_refresh_too_soon(
+            Some(last_refresh),
+            last_refresh + MIN_REFRESH_INTERVAL
+        ));
+    }
 }
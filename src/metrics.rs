@@ -0,0 +1,394 @@
+use jiff::Timestamp;
+use log::{info, trace, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::claude::ExtraUsage;
+
+// Address the exporter listens on by default. Only local clients can scrape it.
+pub const DEFAULT_METRICS_ADDRESS: &str = "127.0.0.1:9877";
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Requests bigger than this are not valid scrapes
+const MAX_REQUEST_SIZE: usize = 8192;
+
+// Usage of an account exported as metrics.
+#[derive(Debug, Clone, Default)]
+pub struct AccountMetrics {
+    pub account_id: String,
+    pub email: String,
+    // Field name, utilization and reset time of every usage period
+    pub periods: Vec<(String, f32, Option<Timestamp>)>,
+    pub extra_usage: ExtraUsage,
+    // Time of the last successful usage poll
+    pub last_poll: Option<Timestamp>,
+}
+
+// Values exported by the metrics endpoint, updated after every usage poll.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsState {
+    pub accounts: Vec<AccountMetrics>,
+    // Failed usage polls of every account identifier since the tray started
+    pub poll_errors: BTreeMap<String, u64>,
+}
+
+// HTTP endpoint serving the usage in the Prometheus text and OpenMetrics formats.
+#[derive(Debug)]
+pub struct MetricsExporter {
+    state: Arc<Mutex<MetricsState>>,
+    task: JoinHandle<()>,
+}
+
+impl MetricsExporter {
+    // Binds the endpoint and starts serving /metrics in the background.
+    pub async fn start(address: SocketAddr) -> Result<Self, String> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| format!("failed to bind metrics endpoint to {}: {}", address, e))?;
+
+        info!("serving metrics on http://{}/metrics", address);
+
+        let state = Arc::new(Mutex::new(MetricsState::default()));
+        let server_state = state.clone();
+
+        let task = tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("failed to accept metrics connection: {}", e);
+                        continue;
+                    }
+                };
+
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &state).await {
+                        trace!("metrics request failed: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { state, task })
+    }
+
+    pub fn update(&self, state: MetricsState) {
+        *self.state.lock().expect("metrics lock poisoned") = state;
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    state: &Mutex<MetricsState>,
+) -> Result<(), String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    // Only the request line and the headers are needed
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream
+            .read(&mut buffer)
+            .await
+            .map_err(|e| format!("failed to read request: {}", e))?;
+
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Err("incomplete request".into());
+        }
+
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+
+    // Prometheus asks for OpenMetrics in the Accept header
+    let openmetrics = request.lines().any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("accept:") && line.contains("application/openmetrics-text")
+    });
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => {
+            let state = state.lock().expect("metrics lock poisoned").clone();
+            let content_type = if openmetrics {
+                OPENMETRICS_CONTENT_TYPE
+            } else {
+                TEXT_CONTENT_TYPE
+            };
+
+            ("200 OK", content_type, render(&state, openmetrics))
+        }
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".into()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".into(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|e| format!("failed to write response: {}", e))
+}
+
+// Metric family written in the exposition formats.
+struct Family<'a> {
+    name: &'a str,
+    kind: &'a str,
+    help: &'a str,
+    samples: Vec<(Vec<(&'a str, &'a str)>, f64)>,
+}
+
+impl Family<'_> {
+    fn write(&self, output: &mut String, openmetrics: bool) {
+        if self.samples.is_empty() {
+            return;
+        }
+
+        // OpenMetrics names counters without their _total suffix
+        let family_name = match (openmetrics, self.kind) {
+            (true, "counter") => self.name.trim_end_matches("_total"),
+            _ => self.name,
+        };
+
+        let _ = writeln!(output, "# HELP {} {}", family_name, self.help);
+        let _ = writeln!(output, "# TYPE {} {}", family_name, self.kind);
+
+        for (labels, value) in &self.samples {
+            let labels = labels
+                .iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let _ = writeln!(output, "{}{{{}}} {}", self.name, labels, value);
+        }
+    }
+}
+
+// Renders the metrics in the OpenMetrics format, or in the
+// Prometheus text format when `openmetrics` is false.
+pub fn render(state: &MetricsState, openmetrics: bool) -> String {
+    let now = Timestamp::now();
+
+    let mut utilization = Family {
+        name: "claude_usage_utilization_percent",
+        kind: "gauge",
+        help: "Utilization of a usage period.",
+        samples: Vec::new(),
+    };
+    let mut reset = Family {
+        name: "claude_usage_reset_seconds",
+        kind: "gauge",
+        help: "Seconds until a usage period resets.",
+        samples: Vec::new(),
+    };
+    let mut extra_used = Family {
+        name: "claude_usage_extra_credits_used",
+        kind: "gauge",
+        help: "Extra usage credits used this month.",
+        samples: Vec::new(),
+    };
+    let mut extra_limit = Family {
+        name: "claude_usage_extra_credits_limit",
+        kind: "gauge",
+        help: "Monthly limit of extra usage credits.",
+        samples: Vec::new(),
+    };
+    let mut last_poll = Family {
+        name: "claude_usage_last_poll_timestamp_seconds",
+        kind: "gauge",
+        help: "Time of the last successful usage poll.",
+        samples: Vec::new(),
+    };
+    let mut poll_errors = Family {
+        name: "claude_usage_poll_errors_total",
+        kind: "counter",
+        help: "Failed usage polls since the tray started.",
+        samples: Vec::new(),
+    };
+
+    for account in &state.accounts {
+        let account_labels = [
+            ("account", account.account_id.as_str()),
+            ("email", account.email.as_str()),
+        ];
+
+        for (period, period_utilization, resets_at) in &account.periods {
+            let mut labels = account_labels.to_vec();
+            labels.push(("period", period.as_str()));
+
+            utilization
+                .samples
+                .push((labels.clone(), *period_utilization as f64));
+
+            if let Some(resets_at) = resets_at {
+                let seconds = resets_at.duration_since(now).as_secs().max(0);
+                reset.samples.push((labels, seconds as f64));
+            }
+        }
+
+        if account.extra_usage.is_enabled {
+            extra_used.samples.push((
+                account_labels.to_vec(),
+                account.extra_usage.used_credits.unwrap_or_default() as f64,
+            ));
+
+            if let Some(limit) = account.extra_usage.monthly_limit {
+                extra_limit
+                    .samples
+                    .push((account_labels.to_vec(), limit as f64));
+            }
+        }
+
+        if let Some(polled_at) = account.last_poll {
+            last_poll
+                .samples
+                .push((account_labels.to_vec(), polled_at.as_second() as f64));
+        }
+    }
+
+    for (account_id, errors) in &state.poll_errors {
+        poll_errors
+            .samples
+            .push((vec![("account", account_id.as_str())], *errors as f64));
+    }
+
+    let mut output = String::new();
+
+    for family in [
+        utilization,
+        reset,
+        extra_used,
+        extra_limit,
+        last_poll,
+        poll_errors,
+    ] {
+        family.write(&mut output, openmetrics);
+    }
+
+    if openmetrics {
+        output.push_str("# EOF\n");
+    }
+
+    output
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Account with an email that needs escaping in the labels
+    fn state() -> MetricsState {
+        MetricsState {
+            accounts: vec![AccountMetrics {
+                account_id: "first".into(),
+                email: "a\"b\\c\nd@example.com".into(),
+                periods: vec![("five_hour".into(), 23.5, None)],
+                extra_usage: ExtraUsage {
+                    is_enabled: true,
+                    monthly_limit: Some(5000),
+                    used_credits: Some(1250),
+                    utilization: Some(25.0),
+                },
+                last_poll: Some("2026-10-16T10:00:00Z".parse().unwrap()),
+            }],
+            poll_errors: BTreeMap::from([("first".into(), 3)]),
+        }
+    }
+
+    #[test]
+    fn render_openmetrics() {
+        let labels = r#"account="first",email="a\"b\\c\nd@example.com""#;
+
+        assert_eq!(
+            render(&state(), true),
+            [
+                "# HELP claude_usage_utilization_percent Utilization of a usage period.".into(),
+                "# TYPE claude_usage_utilization_percent gauge".into(),
+                format!(
+                    "claude_usage_utilization_percent{{{},period=\"five_hour\"}} 23.5",
+                    labels
+                ),
+                "# HELP claude_usage_extra_credits_used Extra usage credits used this month."
+                    .into(),
+                "# TYPE claude_usage_extra_credits_used gauge".into(),
+                format!("claude_usage_extra_credits_used{{{}}} 1250", labels),
+                "# HELP claude_usage_extra_credits_limit Monthly limit of extra usage credits."
+                    .into(),
+                "# TYPE claude_usage_extra_credits_limit gauge".into(),
+                format!("claude_usage_extra_credits_limit{{{}}} 5000", labels),
+                "# HELP claude_usage_last_poll_timestamp_seconds Time of the last successful usage poll."
+                    .into(),
+                "# TYPE claude_usage_last_poll_timestamp_seconds gauge".into(),
+                format!(
+                    "claude_usage_last_poll_timestamp_seconds{{{}}} 1792144800",
+                    labels
+                ),
+                "# HELP claude_usage_poll_errors Failed usage polls since the tray started."
+                    .into(),
+                "# TYPE claude_usage_poll_errors counter".into(),
+                "claude_usage_poll_errors_total{account=\"first\"} 3".into(),
+                "# EOF".into(),
+                String::new(),
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn render_prometheus_text() {
+        let output = render(&state(), false);
+
+        // The counter family keeps its _total suffix, and there is no EOF marker
+        assert!(output.contains("# TYPE claude_usage_poll_errors_total counter\n"));
+        assert!(output.contains("\nclaude_usage_poll_errors_total{account=\"first\"} 3\n"));
+        assert!(!output.contains("# EOF"));
+    }
+
+    #[test]
+    fn render_reset_seconds_of_past_resets() {
+        let mut state = state();
+        state.accounts[0].periods = vec![(
+            "seven_day".into(),
+            50.0,
+            Some("2026-01-01T00:00:00Z".parse().unwrap()),
+        )];
+
+        assert!(
+            render(&state, true)
+                .lines()
+                .any(|line| line.starts_with("claude_usage_reset_seconds{")
+                    && line.ends_with(",period=\"seven_day\"} 0"))
+        );
+    }
+}
@@ -0,0 +1,483 @@
+use jiff::Timestamp;
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::path::Path;
+use zbus::Connection;
+use zvariant::Value;
+
+use crate::claude::ClaudeUsageResponse;
+use crate::forecast::RunOut;
+use crate::i18n::{Language, Text};
+use crate::utils::{format_reset, format_run_out};
+
+// Utilization percentages that trigger a notification when crossed.
+pub const DEFAULT_THRESHOLDS: [f32; 3] = [50.0, 80.0, 95.0];
+
+// Points the utilization needs to go below a threshold before it can
+// notify again, so small variations don't repeat the notification.
+pub const DEFAULT_HYSTERESIS: f32 = 5.0;
+
+// Urgency levels of the notifications specification
+const URGENCY_NORMAL: u8 = 1;
+const URGENCY_CRITICAL: u8 = 2;
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+// Desktop notification ready to be sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub urgency: u8,
+}
+
+impl Notification {
+    // Notification sent when the tray cannot authenticate an account anymore
+    pub fn auth_lost(account: &str, language: Language) -> Self {
+        Self {
+            summary: language.text(Text::LoggedOut).into(),
+            body: format!("{}: {}", account, language.text(Text::AuthLost)),
+            urgency: URGENCY_CRITICAL,
+        }
+    }
+
+    // Notification sent when the usage history is exported from the tray menu
+    pub fn history_exported(path: &Path, language: Language) -> Self {
+        Self {
+            summary: language.text(Text::HistoryExported).into(),
+            body: path.display().to_string(),
+            urgency: URGENCY_NORMAL,
+        }
+    }
+
+    pub fn export_failed(error: &str, language: Language) -> Self {
+        Self {
+            summary: language.text(Text::ExportFailed).into(),
+            body: error.into(),
+            urgency: URGENCY_NORMAL,
+        }
+    }
+
+    // Notification sent when a usage period is projected to run out before it resets
+    pub fn run_out(account: &str, period: &str, run_out: &RunOut, language: Language) -> Self {
+        Self {
+            summary: language.text(Text::RunningOut).into(),
+            body: format!(
+                "{}: {} {}",
+                account,
+                period,
+                format_run_out(run_out, language)
+            ),
+            urgency: URGENCY_NORMAL,
+        }
+    }
+}
+
+// Sends notifications through the org.freedesktop.Notifications service.
+#[derive(Debug, Clone)]
+pub struct DesktopNotifications {
+    connection: Connection,
+}
+
+impl DesktopNotifications {
+    pub async fn connect() -> Result<Self, String> {
+        let connection = Connection::session()
+            .await
+            .map_err(|e| format!("failed to connect to the session bus: {}", e))?;
+
+        Ok(Self::new(connection))
+    }
+
+    // Sends the notifications through `connection` instead of the session bus.
+    pub fn new(connection: Connection) -> Self {
+        Self { connection }
+    }
+
+    pub async fn send(&self, notification: &Notification) -> Result<(), String> {
+        trace!("sending notification: {}", notification.summary);
+
+        let proxy = NotificationsProxy::new(&self.connection)
+            .await
+            .map_err(|e| format!("failed to create notifications proxy: {}", e))?;
+
+        let hints = HashMap::from([("urgency", Value::from(notification.urgency))]);
+
+        proxy
+            .notify(
+                "Claude Tray",
+                0,
+                "dialog-information",
+                &notification.summary,
+                &notification.body,
+                &[],
+                hints,
+                -1,
+            )
+            .await
+            .map_err(|e| format!("failed to send notification: {}", e))?;
+
+        Ok(())
+    }
+
+    // Sends every notification, logging the ones that fail.
+    pub async fn send_all(&self, notifications: &[Notification]) {
+        for notification in notifications {
+            if let Err(e) = self.send(notification).await {
+                warn!("{}", e);
+            }
+        }
+    }
+}
+
+// Notification state of a usage period.
+#[derive(Debug, Default)]
+struct PeriodState {
+    // Number of thresholds already notified
+    level: usize,
+    resets_at: Option<Timestamp>,
+}
+
+// Decides which notifications are sent after each usage poll, remembering
+// the thresholds already notified for every account and usage period.
+#[derive(Debug)]
+pub struct UsageNotifications {
+    // Sorted utilization thresholds
+    thresholds: Vec<f32>,
+    hysteresis: f32,
+    language: Language,
+    // Keyed by account identifier and usage period field name
+    periods: HashMap<(String, String), PeriodState>,
+}
+
+impl UsageNotifications {
+    pub fn new(thresholds: Vec<f32>, hysteresis: f32, language: Language) -> Self {
+        let mut notifications = Self {
+            thresholds: Vec::new(),
+            hysteresis,
+            language,
+            periods: HashMap::new(),
+        };
+        notifications.configure(thresholds, hysteresis, language);
+
+        notifications
+    }
+
+    // Changes the notification options, keeping the thresholds already
+    // notified that still exist so they are not notified again.
+    pub fn configure(&mut self, mut thresholds: Vec<f32>, hysteresis: f32, language: Language) {
+        thresholds.sort_by(f32::total_cmp);
+        thresholds.dedup();
+
+        for state in self.periods.values_mut() {
+            state.level = state.level.min(thresholds.len());
+        }
+
+        self.thresholds = thresholds;
+        self.hysteresis = hysteresis;
+        self.language = language;
+    }
+
+    // Compares the new usage of an account with the previous one, returning
+    // the notifications for the crossed thresholds and the reset limits.
+    pub fn check(
+        &mut self,
+        account_id: &str,
+        account_label: &str,
+        usage: &ClaudeUsageResponse,
+    ) -> Vec<Notification> {
+        let now = Timestamp::now();
+        let mut notifications = Vec::new();
+
+        for bucket in usage.buckets() {
+            let state = self
+                .periods
+                .entry((account_id.to_string(), bucket.key.clone()))
+                .or_default();
+
+            let utilization = bucket.period.utilization;
+            let label = self.language.period_label(&bucket.key, &bucket.label);
+
+            // The period reset since the previous poll
+            if state.resets_at.is_some_and(|resets_at| resets_at <= now) {
+                if state.level > 0 {
+                    notifications.push(Notification {
+                        summary: self.language.text(Text::LimitReset).into(),
+                        body: format!(
+                            "{}: {} {} ({}%)",
+                            account_label,
+                            label,
+                            self.language.text(Text::AvailableAgain),
+                            utilization
+                        ),
+                        urgency: URGENCY_NORMAL,
+                    });
+                }
+
+                state.level = 0;
+            }
+
+            // Thresholds are armed again once the utilization goes clearly below them
+            while state.level > 0
+                && utilization < self.thresholds[state.level - 1] - self.hysteresis
+            {
+                state.level -= 1;
+            }
+
+            let crossed = self
+                .thresholds
+                .iter()
+                .filter(|threshold| utilization >= **threshold)
+                .count();
+
+            if crossed > state.level {
+                let threshold = self.thresholds[crossed - 1];
+                let resets = bucket
+                    .period
+                    .resets_at
+                    .map(|resets_at| format!(", {}", format_reset(resets_at, self.language)))
+                    .unwrap_or_default();
+
+                notifications.push(Notification {
+                    summary: format!("{} {}%", self.language.text(Text::UsageAbove), threshold),
+                    body: format!(
+                        "{}: {} {} {}%{}",
+                        account_label,
+                        label,
+                        self.language.text(Text::UsageAt),
+                        utilization,
+                        resets
+                    ),
+                    urgency: if crossed == self.thresholds.len() {
+                        URGENCY_CRITICAL
+                    } else {
+                        URGENCY_NORMAL
+                    },
+                });
+
+                state.level = crossed;
+            }
+
+            state.resets_at = bucket.period.resets_at;
+        }
+
+        notifications
+    }
+
+    // Forgets the state of an account after it is logged out
+    pub fn forget(&mut self, account_id: &str) {
+        self.periods.retain(|(id, _), _| id != account_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claude::tests::usage;
+    use crate::utils::tests::p2p_connections;
+    use jiff::SignedDuration;
+    use std::sync::{Arc, Mutex};
+    use zbus::interface;
+    use zvariant::OwnedValue;
+
+    fn later() -> Timestamp {
+        Timestamp::now() + SignedDuration::from_hours(2)
+    }
+
+    fn notifications() -> UsageNotifications {
+        UsageNotifications::new(
+            DEFAULT_THRESHOLDS.to_vec(),
+            DEFAULT_HYSTERESIS,
+            Language::English,
+        )
+    }
+
+    fn summaries(notifications: &[Notification]) -> Vec<&str> {
+        notifications
+            .iter()
+            .map(|notification| notification.summary.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn check_notifies_the_highest_crossed_threshold_once() {
+        let mut notifications = notifications();
+
+        assert!(
+            notifications
+                .check("id", "account", &usage(40.0, later()))
+                .is_empty()
+        );
+
+        let crossed = notifications.check("id", "account", &usage(85.0, later()));
+        assert_eq!(summaries(&crossed), ["Claude usage above 80%"]);
+        assert_eq!(crossed[0].urgency, URGENCY_NORMAL);
+
+        assert!(
+            notifications
+                .check("id", "account", &usage(90.0, later()))
+                .is_empty()
+        );
+
+        let crossed = notifications.check("id", "account", &usage(96.0, later()));
+        assert_eq!(summaries(&crossed), ["Claude usage above 95%"]);
+        assert_eq!(crossed[0].urgency, URGENCY_CRITICAL);
+    }
+
+    #[test]
+    fn check_rearms_thresholds_below_the_hysteresis() {
+        let mut notifications = notifications();
+
+        notifications.check("id", "account", &usage(82.0, later()));
+
+        // Within the hysteresis of the 80% threshold
+        notifications.check("id", "account", &usage(76.0, later()));
+        assert!(
+            notifications
+                .check("id", "account", &usage(82.0, later()))
+                .is_empty()
+        );
+
+        // Clearly below it
+        notifications.check("id", "account", &usage(70.0, later()));
+        assert_eq!(
+            summaries(&notifications.check("id", "account", &usage(82.0, later()))),
+            ["Claude usage above 80%"]
+        );
+    }
+
+    #[test]
+    fn check_notifies_the_reset_of_a_notified_period() {
+        let mut notifications = notifications();
+        let past = Timestamp::now() - SignedDuration::from_secs(1);
+
+        notifications.check("id", "account", &usage(97.0, past));
+
+        let reset = notifications.check("id", "account", &usage(0.0, later()));
+        assert_eq!(summaries(&reset), ["Claude usage limit reset"]);
+        assert_eq!(
+            reset[0].body,
+            "account: Current session is available again (0%)"
+        );
+
+        // Periods that were not notified reset silently
+        notifications.check("id", "account", &usage(10.0, past));
+        assert!(
+            notifications
+                .check("id", "account", &usage(0.0, later()))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn check_keeps_accounts_apart() {
+        let mut notifications = notifications();
+
+        notifications.check("first", "first", &usage(60.0, later()));
+
+        assert_eq!(
+            summaries(&notifications.check("second", "second", &usage(60.0, later()))),
+            ["Claude usage above 50%"]
+        );
+    }
+
+    #[test]
+    fn configure_with_fewer_thresholds() {
+        let mut notifications = notifications();
+
+        notifications.check("id", "account", &usage(97.0, later()));
+        notifications.configure(vec![90.0], DEFAULT_HYSTERESIS, Language::English);
+
+        // The notified level is kept within the new thresholds
+        assert!(
+            notifications
+                .check("id", "account", &usage(97.0, later()))
+                .is_empty()
+        );
+
+        notifications.check("id", "account", &usage(80.0, later()));
+        assert_eq!(
+            summaries(&notifications.check("id", "account", &usage(91.0, later()))),
+            ["Claude usage above 90%"]
+        );
+    }
+
+    // Stand-in notifications server recording the summary, body and urgency it receives
+    #[derive(Default)]
+    struct FakeNotifications {
+        received: Arc<Mutex<Vec<(String, String, u8)>>>,
+    }
+
+    #[interface(name = "org.freedesktop.Notifications")]
+    impl FakeNotifications {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            _app_name: &str,
+            _replaces_id: u32,
+            _app_icon: &str,
+            summary: &str,
+            body: &str,
+            _actions: Vec<String>,
+            hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let urgency = hints
+                .get("urgency")
+                .and_then(|urgency| u8::try_from(urgency).ok())
+                .unwrap_or_default();
+
+            let mut received = self.received.lock().unwrap();
+            received.push((summary.into(), body.into(), urgency));
+            received.len() as u32
+        }
+    }
+
+    #[tokio::test]
+    async fn send_all_through_the_notifications_service() {
+        let service = FakeNotifications::default();
+        let received = service.received.clone();
+        let (client, server) = p2p_connections().await;
+        server
+            .object_server()
+            .at("/org/freedesktop/Notifications", service)
+            .await
+            .unwrap();
+
+        DesktopNotifications::new(client)
+            .send_all(&[
+                Notification::auth_lost("account", Language::English),
+                Notification::export_failed("disk full", Language::English),
+            ])
+            .await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].2, URGENCY_CRITICAL);
+        assert_eq!(
+            (
+                received[1].0.as_str(),
+                received[1].1.as_str(),
+                received[1].2
+            ),
+            ("Usage history export failed", "disk full", URGENCY_NORMAL)
+        );
+    }
+}
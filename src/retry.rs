@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use crate::config::RetryConfig;
+
+// Delays between the retries of a failed usage poll. They grow exponentially up
+// to a cap, with a random jitter so several accounts or machines that lost the
+// connection at the same time don't retry at the same time.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    // Retries scheduled since the last successful poll
+    attempts: u32,
+}
+
+impl Backoff {
+    // Delay before the next retry, or `None` when every attempt was used.
+    // It is between half and the whole exponential delay
+    pub fn next_delay(&mut self, config: &RetryConfig) -> Option<Duration> {
+        self.next_delay_with_jitter(config, rand::random_range(0.5..=1.0))
+    }
+
+    // Delay before the next retry, scaled by `jitter`
+    fn next_delay_with_jitter(&mut self, config: &RetryConfig, jitter: f64) -> Option<Duration> {
+        if self.attempts >= config.max_attempts {
+            return None;
+        }
+
+        let delay = config
+            .initial_delay()
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(config.max_delay());
+        self.attempts += 1;
+
+        Some(delay.mul_f64(jitter))
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RetryConfig {
+        RetryConfig {
+            initial_delay_secs: 10,
+            max_delay_secs: 300,
+            max_attempts: 7,
+        }
+    }
+
+    fn delays(backoff: &mut Backoff, jitter: f64) -> Vec<Option<u64>> {
+        (0..8)
+            .map(|_| {
+                backoff
+                    .next_delay_with_jitter(&config(), jitter)
+                    .map(|delay| delay.as_secs())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn next_delay_doubles_up_to_the_max_delay() {
+        let mut backoff = Backoff::default();
+
+        assert_eq!(
+            delays(&mut backoff, 1.0),
+            [
+                Some(10),
+                Some(20),
+                Some(40),
+                Some(80),
+                Some(160),
+                Some(300),
+                Some(300),
+                None
+            ]
+        );
+        assert_eq!(backoff.attempts(), 7);
+    }
+
+    #[test]
+    fn next_delay_scaled_by_the_jitter() {
+        let mut backoff = Backoff::default();
+
+        assert_eq!(
+            delays(&mut backoff, 0.5)[..3],
+            [Some(5), Some(10), Some(20)]
+        );
+    }
+
+    #[test]
+    fn next_delay_jitter_bounds() {
+        for _ in 0..100 {
+            let mut backoff = Backoff { attempts: 2 };
+
+            let delay = backoff.next_delay(&config()).unwrap();
+
+            assert!((Duration::from_secs(20)..=Duration::from_secs(40)).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn reset_starts_from_the_initial_delay() {
+        let mut backoff = Backoff::default();
+        delays(&mut backoff, 1.0);
+
+        backoff.reset();
+
+        assert_eq!(backoff.attempts(), 0);
+        assert_eq!(
+            backoff.next_delay_with_jitter(&config(), 1.0),
+            Some(Duration::from_secs(10))
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use futures_util::StreamExt;
+use log::{info, trace};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use zbus::Connection;
+use zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Type, Value};
+
+use crate::claude::ClaudeCredentials;
+use crate::credentials::parse_credentials;
+use crate::error::ClaudeError;
+
+// Collection used by the Secret Service implementations as the user keyring.
+pub const DEFAULT_COLLECTION_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+
+// Attributes used to find the credentials item within the keyring.
+const ITEM_ATTRIBUTES: [(&str, &str); 2] = [("application", "claude-tray"), ("type", "oauth")];
+
+const ITEM_LABEL: &str = "Claude Tray OAuth credentials";
+
+// Secret structure defined by the Secret Service API (oayays).
+#[derive(Debug, Deserialize, Serialize, Type)]
+pub struct Secret {
+    pub session: OwnedObjectPath,
+    pub parameters: Vec<u8>,
+    pub value: Vec<u8>,
+    pub content_type: String,
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Secret.Service",
+    default_service = "org.freedesktop.secrets",
+    default_path = "/org/freedesktop/secrets"
+)]
+trait Service {
+    fn open_session(
+        &self,
+        algorithm: &str,
+        input: &Value<'_>,
+    ) -> zbus::Result<(OwnedValue, OwnedObjectPath)>;
+
+    fn search_items(
+        &self,
+        attributes: HashMap<&str, &str>,
+    ) -> zbus::Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>)>;
+
+    fn unlock(
+        &self,
+        objects: &[ObjectPath<'_>],
+    ) -> zbus::Result<(Vec<OwnedObjectPath>, OwnedObjectPath)>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Secret.Collection",
+    default_service = "org.freedesktop.secrets"
+)]
+trait Collection {
+    fn create_item(
+        &self,
+        properties: HashMap<&str, Value<'_>>,
+        secret: &Secret,
+        replace: bool,
+    ) -> zbus::Result<(OwnedObjectPath, OwnedObjectPath)>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Secret.Item",
+    default_service = "org.freedesktop.secrets"
+)]
+trait Item {
+    fn get_secret(&self, session: &ObjectPath<'_>) -> zbus::Result<Secret>;
+
+    fn delete(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Secret.Prompt",
+    default_service = "org.freedesktop.secrets"
+)]
+trait Prompt {
+    fn prompt(&self, window_id: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn completed(&self, dismissed: bool, result: Value<'_>) -> zbus::Result<()>;
+}
+
+// Credentials backend that stores the tokens in the user keyring through
+// the Secret Service API (org.freedesktop.secrets).
+#[derive(Debug, Clone)]
+pub struct SecretServiceStore {
+    connection: Connection,
+}
+
+impl SecretServiceStore {
+    // Connects to the Secret Service of the current session bus. It fails
+    // when no provider (gnome-keyring, kwallet, keepassxc...) is available.
+    pub async fn connect() -> Result<Self, ClaudeError> {
+        let connection = Connection::session().await.map_err(|e| {
+            ClaudeError::Keyring(format!("failed to connect to the session bus: {}", e))
+        })?;
+
+        let store = Self::new(connection);
+
+        // Opening a session checks that a provider is really listening
+        store.open_session().await?;
+
+        Ok(store)
+    }
+
+    // Creates the store over an existing connection. It allows using any
+    // bus that exposes the org.freedesktop.secrets service.
+    pub fn new(connection: Connection) -> Self {
+        Self { connection }
+    }
+
+    async fn service(&self) -> Result<ServiceProxy<'static>, ClaudeError> {
+        ServiceProxy::new(&self.connection).await.map_err(|e| {
+            ClaudeError::Keyring(format!("failed to create secret service proxy: {}", e))
+        })
+    }
+
+    // Opens a session without transport encryption. The secret
+    // never leaves the local session bus.
+    async fn open_session(&self) -> Result<OwnedObjectPath, ClaudeError> {
+        let (_, session) = self
+            .service()
+            .await?
+            .open_session("plain", &Value::from(""))
+            .await
+            .map_err(|e| {
+                ClaudeError::Keyring(format!("failed to open secret service session: {}", e))
+            })?;
+
+        Ok(session)
+    }
+
+    // Waits for the result of a prompt (e.g. unlocking the keyring).
+    // A "/" path means that no prompt is needed.
+    async fn complete_prompt(&self, prompt: OwnedObjectPath) -> Result<(), ClaudeError> {
+        if prompt.as_str() == "/" {
+            return Ok(());
+        }
+
+        trace!("waiting for secret service prompt {}", prompt.as_str());
+
+        let proxy = PromptProxy::builder(&self.connection)
+            .path(prompt)
+            .map_err(|e| ClaudeError::Keyring(format!("invalid prompt path: {}", e)))?
+            .build()
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to create prompt proxy: {}", e)))?;
+
+        let mut completed = proxy
+            .receive_completed()
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to subscribe to prompt: {}", e)))?;
+
+        proxy
+            .prompt("")
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to show prompt: {}", e)))?;
+
+        let signal = completed.next().await.ok_or(ClaudeError::Keyring(
+            "prompt closed without completing".into(),
+        ))?;
+
+        let args = signal
+            .args()
+            .map_err(|e| ClaudeError::Keyring(format!("invalid prompt result: {}", e)))?;
+
+        if args.dismissed {
+            return Err(ClaudeError::Keyring(
+                "secret service prompt dismissed".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Looks for the credentials item, unlocking it if needed.
+    async fn find_item(&self) -> Result<Option<OwnedObjectPath>, ClaudeError> {
+        let service = self.service().await?;
+
+        let (unlocked, locked) = service
+            .search_items(HashMap::from(ITEM_ATTRIBUTES))
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to search keyring items: {}", e)))?;
+
+        if let Some(item) = unlocked.into_iter().next() {
+            return Ok(Some(item));
+        }
+
+        let Some(item) = locked.into_iter().next() else {
+            return Ok(None);
+        };
+
+        info!("keyring is locked. requesting unlock");
+
+        self.unlock(item.as_ref()).await?;
+
+        Ok(Some(item))
+    }
+
+    // Unlocks an item or a collection, waiting for the user to enter the
+    // password when the provider asks for it.
+    async fn unlock(&self, object: ObjectPath<'_>) -> Result<(), ClaudeError> {
+        let (_, prompt) = self
+            .service()
+            .await?
+            .unlock(&[object])
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to unlock keyring: {}", e)))?;
+
+        self.complete_prompt(prompt).await
+    }
+
+    async fn item(&self, path: OwnedObjectPath) -> Result<ItemProxy<'static>, ClaudeError> {
+        ItemProxy::builder(&self.connection)
+            .path(path)
+            .map_err(|e| ClaudeError::Keyring(format!("invalid item path: {}", e)))?
+            .build()
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to create item proxy: {}", e)))
+    }
+
+    pub async fn load(&self) -> Result<Vec<ClaudeCredentials>, ClaudeError> {
+        let Some(path) = self.find_item().await? else {
+            return Ok(Vec::new());
+        };
+
+        let session = self.open_session().await?;
+
+        let secret = self
+            .item(path)
+            .await?
+            .get_secret(&session.as_ref())
+            .await
+            .map_err(|e| ClaudeError::Keyring(format!("failed to read keyring secret: {}", e)))?;
+
+        parse_credentials(&secret.value)
+    }
+
+    pub async fn save(&self, credentials: &[ClaudeCredentials]) -> Result<(), ClaudeError> {
+        let session = self.open_session().await?;
+
+        let value = serde_json::to_vec(credentials)
+            .map_err(|e| ClaudeError::Keyring(format!("failed to serialize credentials: {}", e)))?;
+
+        let secret = Secret {
+            session,
+            parameters: Vec::new(),
+            value,
+            content_type: "application/json".into(),
+        };
+
+        let properties = HashMap::from([
+            ("org.freedesktop.Secret.Item.Label", Value::from(ITEM_LABEL)),
+            (
+                "org.freedesktop.Secret.Item.Attributes",
+                Value::from(HashMap::from(ITEM_ATTRIBUTES)),
+            ),
+        ]);
+
+        // Items cannot be created in a locked collection. Unlocking an
+        // unlocked collection finishes right away, without prompts
+        self.unlock(ObjectPath::from_static_str_unchecked(
+            DEFAULT_COLLECTION_PATH,
+        ))
+        .await?;
+
+        let collection = CollectionProxy::builder(&self.connection)
+            .path(DEFAULT_COLLECTION_PATH)
+            .map_err(|e| ClaudeError::Keyring(format!("invalid collection path: {}", e)))?
+            .build()
+            .await
+            .map_err(|e| {
+                ClaudeError::Keyring(format!("failed to create collection proxy: {}", e))
+            })?;
+
+        let (_, prompt) = collection
+            .create_item(properties, &secret, true)
+            .await
+            .map_err(|e| {
+                ClaudeError::Keyring(format!("failed to store credentials in keyring: {}", e))
+            })?;
+
+        self.complete_prompt(prompt).await
+    }
+
+    pub async fn delete(&self) -> Result<(), ClaudeError> {
+        let Some(path) = self.find_item().await? else {
+            return Ok(());
+        };
+
+        let prompt =
+            self.item(path).await?.delete().await.map_err(|e| {
+                ClaudeError::Keyring(format!("failed to delete keyring item: {}", e))
+            })?;
+
+        self.complete_prompt(prompt).await
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::utils::tests::p2p_connections;
+    use std::sync::{Arc, Mutex};
+    use zbus::{fdo, interface, object_server::SignalEmitter};
+
+    const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+    const SESSION_PATH: &str = "/org/freedesktop/secrets/session/1";
+    const ITEM_PATH: &str = "/org/freedesktop/secrets/collection/login/1";
+    const PROMPT_PATH: &str = "/org/freedesktop/secrets/prompt/1";
+
+    // Value of the only item of the stand-in provider, once it is created
+    type StoredSecret = Arc<Mutex<Option<Vec<u8>>>>;
+
+    // Whether the default collection is locked. It is unlocked through a prompt
+    type Locked = Arc<Mutex<bool>>;
+
+    fn path(path: &'static str) -> OwnedObjectPath {
+        ObjectPath::from_static_str_unchecked(path).into()
+    }
+
+    // Stand-in Secret Service provider with a single unlocked item. Unlocking
+    // its collection shows a prompt that is completed right away
+    struct FakeService {
+        secret: StoredSecret,
+        locked: Locked,
+    }
+
+    #[interface(name = "org.freedesktop.Secret.Service")]
+    impl FakeService {
+        fn open_session(
+            &self,
+            _algorithm: &str,
+            _input: Value<'_>,
+        ) -> fdo::Result<(OwnedValue, OwnedObjectPath)> {
+            let output = Value::from("")
+                .try_into()
+                .map_err(|e: zvariant::Error| fdo::Error::Failed(e.to_string()))?;
+
+            Ok((output, path(SESSION_PATH)))
+        }
+
+        fn search_items(
+            &self,
+            _attributes: HashMap<String, String>,
+        ) -> (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) {
+            let items = match *self.secret.lock().unwrap() {
+                Some(_) => vec![path(ITEM_PATH)],
+                None => Vec::new(),
+            };
+
+            (items, Vec::new())
+        }
+
+        fn unlock(&self, objects: Vec<OwnedObjectPath>) -> (Vec<OwnedObjectPath>, OwnedObjectPath) {
+            let is_collection_locked = *self.locked.lock().unwrap()
+                && objects
+                    .iter()
+                    .any(|object| object.as_str() == DEFAULT_COLLECTION_PATH);
+
+            if is_collection_locked {
+                (Vec::new(), path(PROMPT_PATH))
+            } else {
+                (objects, path("/"))
+            }
+        }
+    }
+
+    struct FakeCollection {
+        secret: StoredSecret,
+        locked: Locked,
+    }
+
+    #[interface(name = "org.freedesktop.Secret.Collection")]
+    impl FakeCollection {
+        fn create_item(
+            &self,
+            _properties: HashMap<String, OwnedValue>,
+            secret: Secret,
+            _replace: bool,
+        ) -> fdo::Result<(OwnedObjectPath, OwnedObjectPath)> {
+            if *self.locked.lock().unwrap() {
+                return Err(fdo::Error::Failed("collection is locked".into()));
+            }
+
+            *self.secret.lock().unwrap() = Some(secret.value);
+
+            Ok((path(ITEM_PATH), path("/")))
+        }
+    }
+
+    // Prompt to unlock the collection, accepted as soon as it is shown
+    struct FakePrompt {
+        locked: Locked,
+    }
+
+    #[interface(name = "org.freedesktop.Secret.Prompt")]
+    impl FakePrompt {
+        async fn prompt(
+            &self,
+            _window_id: &str,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        ) -> fdo::Result<()> {
+            *self.locked.lock().unwrap() = false;
+
+            Self::completed(&emitter, false, Value::from(Vec::<OwnedObjectPath>::new())).await?;
+
+            Ok(())
+        }
+
+        #[zbus(signal)]
+        async fn completed(
+            emitter: &SignalEmitter<'_>,
+            dismissed: bool,
+            result: Value<'_>,
+        ) -> zbus::Result<()>;
+    }
+
+    struct FakeItem {
+        secret: StoredSecret,
+    }
+
+    #[interface(name = "org.freedesktop.Secret.Item")]
+    impl FakeItem {
+        fn get_secret(&self, session: OwnedObjectPath) -> fdo::Result<Secret> {
+            let value = self
+                .secret
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or(fdo::Error::UnknownObject("item deleted".into()))?;
+
+            Ok(Secret {
+                session,
+                parameters: Vec::new(),
+                value,
+                content_type: "application/json".into(),
+            })
+        }
+
+        fn delete(&self) -> OwnedObjectPath {
+            *self.secret.lock().unwrap() = None;
+
+            path("/")
+        }
+    }
+
+    // Store connected to a stand-in provider through a peer-to-peer D-Bus
+    // connection. The provider side of the connection is returned too,
+    // since the provider stops when it is dropped.
+    pub async fn fake_store() -> (SecretServiceStore, Connection) {
+        fake_provider(false).await
+    }
+
+    async fn fake_provider(is_locked: bool) -> (SecretServiceStore, Connection) {
+        let secret = StoredSecret::default();
+        let locked = Locked::new(Mutex::new(is_locked));
+        let (client, server) = p2p_connections().await;
+        let objects = server.object_server();
+
+        objects
+            .at(
+                SERVICE_PATH,
+                FakeService {
+                    secret: secret.clone(),
+                    locked: locked.clone(),
+                },
+            )
+            .await
+            .unwrap();
+        objects
+            .at(
+                DEFAULT_COLLECTION_PATH,
+                FakeCollection {
+                    secret: secret.clone(),
+                    locked: locked.clone(),
+                },
+            )
+            .await
+            .unwrap();
+        objects.at(ITEM_PATH, FakeItem { secret }).await.unwrap();
+        objects
+            .at(PROMPT_PATH, FakePrompt { locked })
+            .await
+            .unwrap();
+
+        (SecretServiceStore::new(client), server)
+    }
+
+    // Credentials of an account whose access token is "<account_id>-<expires_at>"
+    pub fn credentials(account_id: &str, expires_at: u64) -> ClaudeCredentials {
+        serde_json::from_value(serde_json::json!({
+            "access_token": format!("{}-{}", account_id, expires_at),
+            "refresh_token": format!("{}-refresh", account_id),
+            "expires_at": expires_at,
+            "account": {
+                "uuid": account_id,
+                "email_address": format!("{}@example.com", account_id),
+                "organization_name": null,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn load_without_item() {
+        let (store, _provider) = fake_store().await;
+
+        assert!(store.load().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn save_and_load_credentials() {
+        let (store, _provider) = fake_store().await;
+
+        store
+            .save(&[credentials("first", 100), credentials("second", 100)])
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = store
+            .load()
+            .await
+            .unwrap()
+            .iter()
+            .map(ClaudeCredentials::id)
+            .collect();
+        assert_eq!(ids, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn delete_credentials() {
+        let (store, _provider) = fake_store().await;
+
+        store.save(&[credentials("first", 100)]).await.unwrap();
+        store.delete().await.unwrap();
+
+        assert!(store.load().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn save_unlocks_the_collection() {
+        let (store, _provider) = fake_provider(true).await;
+
+        store.save(&[credentials("first", 100)]).await.unwrap();
+
+        let ids: Vec<_> = store
+            .load()
+            .await
+            .unwrap()
+            .iter()
+            .map(ClaudeCredentials::id)
+            .collect();
+        assert_eq!(ids, ["first"]);
+    }
+}
@@ -0,0 +1,254 @@
+use futures_util::StreamExt;
+use log::{trace, warn};
+use tokio::sync::mpsc;
+use zbus::Connection;
+
+use crate::TrayMessage;
+
+// NetworkManager connectivity without any network connection. Limited and
+// captive portal connectivity keep polling, since proxies and connectivity
+// checks blocked by the network report them while the API is reachable
+const CONNECTIVITY_NONE: u32 = 1;
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Login1Manager {
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+trait NetworkManager {
+    #[zbus(property)]
+    fn connectivity(&self) -> zbus::Result<u32>;
+}
+
+// Forwards the suspend and resume of the system (logind) and the changes of the
+// network connectivity (NetworkManager) to the tray, so the usage is not polled
+// while it would fail and is polled right away when it can be again. Each
+// service is optional, and it fails only without a system bus.
+pub async fn watch(notifier: mpsc::Sender<TrayMessage>) -> Result<(), String> {
+    let connection = Connection::system()
+        .await
+        .map_err(|e| format!("failed to connect to the system bus: {}", e))?;
+
+    match watch_sleep(&connection, notifier.clone()).await {
+        Ok(()) => trace!("watching system sleep through logind"),
+        Err(e) => warn!("logind not available: {}", e),
+    }
+
+    match watch_connectivity(&connection, notifier).await {
+        Ok(()) => trace!("watching network connectivity through networkmanager"),
+        Err(e) => warn!("networkmanager not available: {}", e),
+    }
+
+    Ok(())
+}
+
+async fn watch_sleep(
+    connection: &Connection,
+    notifier: mpsc::Sender<TrayMessage>,
+) -> Result<(), String> {
+    let proxy = Login1ManagerProxy::new(connection)
+        .await
+        .map_err(|e| format!("failed to create logind proxy: {}", e))?;
+
+    let mut signals = proxy
+        .receive_prepare_for_sleep()
+        .await
+        .map_err(|e| format!("failed to subscribe to logind: {}", e))?;
+
+    tokio::spawn(async move {
+        while let Some(signal) = signals.next().await {
+            let Ok(args) = signal.args() else {
+                continue;
+            };
+
+            // `start` is true before suspending and false after resuming
+            if notifier.send(TrayMessage::Sleep(args.start)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn watch_connectivity(
+    connection: &Connection,
+    notifier: mpsc::Sender<TrayMessage>,
+) -> Result<(), String> {
+    let proxy = NetworkManagerProxy::new(connection)
+        .await
+        .map_err(|e| format!("failed to create networkmanager proxy: {}", e))?;
+
+    // Reading the current value checks that NetworkManager is running
+    let connectivity = proxy
+        .connectivity()
+        .await
+        .map_err(|e| format!("failed to read connectivity: {}", e))?;
+
+    let mut changes = proxy.receive_connectivity_changed().await;
+
+    tokio::spawn(async move {
+        let mut is_connected = connectivity != CONNECTIVITY_NONE;
+
+        if !is_connected
+            && notifier
+                .send(TrayMessage::Connectivity(false))
+                .await
+                .is_err()
+        {
+            return;
+        }
+
+        while let Some(change) = changes.next().await {
+            let Ok(connectivity) = change.get().await else {
+                continue;
+            };
+
+            // Only changes between connected and disconnected are forwarded
+            if is_connected == (connectivity != CONNECTIVITY_NONE) {
+                continue;
+            }
+            is_connected = !is_connected;
+
+            if notifier
+                .send(TrayMessage::Connectivity(is_connected))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::p2p_connections;
+    use std::time::Duration;
+    use zbus::{interface, object_server::SignalEmitter};
+
+    const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+    const NETWORK_MANAGER_PATH: &str = "/org/freedesktop/NetworkManager";
+
+    // Connectivity reported when the network is limited and when it is full
+    const CONNECTIVITY_LIMITED: u32 = 3;
+    const CONNECTIVITY_FULL: u32 = 4;
+
+    struct FakeLogin1;
+
+    #[interface(name = "org.freedesktop.login1.Manager")]
+    impl FakeLogin1 {
+        #[zbus(signal)]
+        async fn prepare_for_sleep(emitter: &SignalEmitter<'_>, start: bool) -> zbus::Result<()>;
+    }
+
+    struct FakeNetworkManager {
+        connectivity: u32,
+    }
+
+    #[interface(name = "org.freedesktop.NetworkManager")]
+    impl FakeNetworkManager {
+        #[zbus(property)]
+        fn connectivity(&self) -> u32 {
+            self.connectivity
+        }
+    }
+
+    async fn next_message(messages: &mut mpsc::Receiver<TrayMessage>) -> Option<TrayMessage> {
+        tokio::time::timeout(Duration::from_millis(500), messages.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn forward_sleep_and_resume() {
+        let (client, server) = p2p_connections().await;
+        server
+            .object_server()
+            .at(LOGIN1_PATH, FakeLogin1)
+            .await
+            .unwrap();
+        let (notifier, mut messages) = mpsc::channel(4);
+
+        watch_sleep(&client, notifier).await.unwrap();
+
+        let emitter = SignalEmitter::new(&server, LOGIN1_PATH).unwrap();
+        FakeLogin1::prepare_for_sleep(&emitter, true).await.unwrap();
+        FakeLogin1::prepare_for_sleep(&emitter, false)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_message(&mut messages).await,
+            Some(TrayMessage::Sleep(true))
+        ));
+        assert!(matches!(
+            next_message(&mut messages).await,
+            Some(TrayMessage::Sleep(false))
+        ));
+    }
+
+    #[tokio::test]
+    async fn forward_only_connectivity_losses_and_recoveries() {
+        let (client, server) = p2p_connections().await;
+        server
+            .object_server()
+            .at(
+                NETWORK_MANAGER_PATH,
+                FakeNetworkManager {
+                    connectivity: CONNECTIVITY_NONE,
+                },
+            )
+            .await
+            .unwrap();
+        let network_manager = server
+            .object_server()
+            .interface::<_, FakeNetworkManager>(NETWORK_MANAGER_PATH)
+            .await
+            .unwrap();
+        let (notifier, mut messages) = mpsc::channel(4);
+
+        watch_connectivity(&client, notifier).await.unwrap();
+
+        // Starting without connection is forwarded right away
+        assert!(matches!(
+            next_message(&mut messages).await,
+            Some(TrayMessage::Connectivity(false))
+        ));
+
+        // Limited connectivity counts as connected, so the change to full is not forwarded
+        for (connectivity, message) in [
+            (CONNECTIVITY_LIMITED, Some(true)),
+            (CONNECTIVITY_FULL, None),
+            (CONNECTIVITY_NONE, Some(false)),
+        ] {
+            let mut interface = network_manager.get_mut().await;
+            interface.connectivity = connectivity;
+            interface
+                .connectivity_changed(network_manager.signal_emitter())
+                .await
+                .unwrap();
+            drop(interface);
+
+            let forwarded = match next_message(&mut messages).await {
+                Some(TrayMessage::Connectivity(is_connected)) => Some(is_connected),
+                _ => None,
+            };
+            assert_eq!(forwarded, message, "connectivity {}", connectivity);
+        }
+    }
+}
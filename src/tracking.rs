@@ -0,0 +1,435 @@
+use jiff::{SignedDuration, Timestamp};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::claude::{self, ClaudeCredentials, ClaudeUsageResponse, CredentialSource};
+use crate::error::ClaudeError;
+use crate::history::{UsageHistory, UsageSample};
+use crate::notifications::Notification;
+use crate::retry::Backoff;
+use crate::{AppTray, TrayHandle, TrayMessage, publish_state};
+
+// Time waited after a usage period resets before polling the usage again,
+// giving the API time to reflect the reset
+const RESET_POLL_DELAY: Duration = Duration::from_secs(10);
+
+// Time waited before refreshing the access token again when the
+// refresh failed without rejecting the credentials
+const REFRESH_RETRY_DELAY: Duration = Duration::from_mins(1);
+
+// Result of the last usage polls of an account
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PollState {
+    #[default]
+    Online,
+    // The last poll failed and it is retried at `retry_at`
+    Retrying {
+        attempt: u32,
+        max_attempts: u32,
+        retry_at: Timestamp,
+    },
+    // Every retry failed. The usage is polled again at the usual interval
+    Offline,
+    // The API asked to back off. The usage is not polled before `until`
+    RateLimited {
+        until: Timestamp,
+    },
+    // The credentials shared with Claude Code were rejected. The usage is
+    // not polled until Claude Code renews them and its file is reloaded
+    WaitingForClaudeCode,
+}
+
+// Starts the tracking task of an account, replacing the previous one if any.
+pub async fn start_usage_tracking(
+    handle: &TrayHandle,
+    tracking_tasks: &mut HashMap<String, tokio::task::JoinHandle<()>>,
+    account_id: String,
+) {
+    if let Some(task) = tracking_tasks.remove(&account_id) {
+        task.abort();
+    }
+
+    match usage_tracking_task(handle, account_id.clone()).await {
+        Ok(task) => {
+            tracking_tasks.insert(account_id, task);
+        }
+        Err(e) => log::error!("failed to start usage tracking: {}", e),
+    }
+
+    publish_state(handle).await;
+}
+
+async fn usage_tracking_task(
+    handle: &TrayHandle,
+    account_id: String,
+) -> Result<tokio::task::JoinHandle<()>, String> {
+    let handle_tracking = handle.clone();
+
+    let Some((mut credentials, mut poll_interval, mut refresh, mut paused)) = handle
+        .update(|tray: &mut AppTray| {
+            tray.account_mut(&account_id)
+                .map(|account| account.credentials.clone())
+                .map(|credentials| {
+                    (
+                        credentials,
+                        tray.poll_interval.subscribe(),
+                        tray.refresh.subscribe(),
+                        tray.paused.subscribe(),
+                    )
+                })
+        })
+        .await
+        .flatten()
+    else {
+        return Err("no credentials available".into());
+    };
+
+    let tracking_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(*poll_interval.borrow_and_update());
+        let mut next_reset: Option<Timestamp> = None;
+        // A failed refresh is not retried before this time
+        let mut refresh_retry_at = tokio::time::Instant::now();
+        let mut backoff = Backoff::default();
+        // Refreshes are not polled before the time the API asked to wait
+        let mut rate_limited_until = tokio::time::Instant::now();
+
+        let tracking_result: Result<(), ClaudeError> = {
+            loop {
+                // The access token is refreshed shortly before it expires, even
+                // if the next usage poll is not due yet
+                let refresh_in = credentials.time_until_refresh().map(|refresh_in| {
+                    refresh_in.max(refresh_retry_at - tokio::time::Instant::now())
+                });
+                // The usage is polled right after a period resets
+                let reset_in = next_reset.map(|resets_at| {
+                    Duration::try_from(resets_at.duration_since(Timestamp::now()))
+                        .unwrap_or_default()
+                        + RESET_POLL_DELAY
+                });
+                // Nothing is requested while the polls are paused
+                let is_paused = *paused.borrow_and_update();
+
+                tokio::select! {
+                    biased;
+                    _ = tokio::time::sleep(refresh_in.unwrap_or_default()), if refresh_in.is_some() && !is_paused => {
+                        match refresh_tracking_credentials(&handle_tracking, &credentials).await {
+                            Ok(refreshed) => credentials = refreshed,
+                            // Claude Code renews its own token, so it is not given up on
+                            Err(e @ ClaudeError::Unauthorized(_)) if credentials.source != CredentialSource::ClaudeCode => break Err(e),
+                            // The current token is still used until it is rejected
+                            Err(e) => {
+                                log::warn!("failed to refresh access token of {}: {}", account_id, e);
+
+                                let retry_delay = match e {
+                                    ClaudeError::RateLimited { retry_after: Some(retry_after), .. } => {
+                                        retry_after.max(REFRESH_RETRY_DELAY)
+                                    }
+                                    _ => REFRESH_RETRY_DELAY,
+                                };
+                                refresh_retry_at = tokio::time::Instant::now() + retry_delay;
+                            }
+                        }
+                        continue;
+                    }
+                    _ = tokio::time::sleep(reset_in.unwrap_or_default()), if reset_in.is_some() && !is_paused => {
+                        // The reset is polled once. If the poll fails, the retries follow the backoff
+                        next_reset = None;
+
+                        // The next poll already waits for the time the API asked
+                        if tokio::time::Instant::now() < rate_limited_until {
+                            log::trace!("usage requests of {} are rate limited. waiting to poll the reset", account_id);
+                            continue;
+                        }
+
+                        log::trace!("usage period reset. polling usage");
+                        interval.reset();
+                    }
+                    // The next poll is rescheduled with the new interval
+                    Ok(()) = poll_interval.changed() => {
+                        let period = *poll_interval.borrow_and_update();
+                        log::trace!("polling usage of {} every {:?}", account_id, period);
+
+                        interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                        continue;
+                    }
+                    Ok(()) = paused.changed() => continue,
+                    // The usage is polled right away and the next poll is rescheduled
+                    Ok(()) = refresh.changed(), if !is_paused => {
+                        if tokio::time::Instant::now() < rate_limited_until {
+                            log::trace!("usage requests of {} are rate limited. ignoring refresh", account_id);
+                            continue;
+                        }
+
+                        interval.reset();
+                    }
+                    _ = interval.tick(), if !is_paused => {}
+                }
+
+                log::trace!("getting usage data of {} from claude api", account_id);
+
+                let usage = match poll_usage(&handle_tracking, &account_id, &mut credentials).await
+                {
+                    Ok(usage) => usage,
+                    // The account is logged out only when its credentials are rejected
+                    Err(e @ ClaudeError::Unauthorized(_)) => break Err(e),
+                    // Network, server and format errors are transient. The
+                    // previous usage is kept while the poll is retried
+                    Err(e) => {
+                        let retry = handle_tracking
+                            .update(|tray: &mut AppTray| tray.config.retry.clone())
+                            .await
+                            .unwrap_or_default();
+
+                        let poll_state = match (&e, backoff.next_delay(&retry)) {
+                            // The time asked by the server is waited, but never less than
+                            // the backoff, so the API is not polled again too soon
+                            (ClaudeError::RateLimited { retry_after, .. }, backoff_delay) => {
+                                let delay = retry_after
+                                    .unwrap_or_default()
+                                    .max(backoff_delay.unwrap_or(*poll_interval.borrow()));
+                                log::warn!(
+                                    "usage requests of {} are rate limited: {}. polling again in {:?}",
+                                    account_id,
+                                    e,
+                                    delay
+                                );
+                                interval.reset_after(delay);
+                                rate_limited_until = tokio::time::Instant::now() + delay;
+
+                                PollState::RateLimited {
+                                    until: Timestamp::now()
+                                        + SignedDuration::try_from(delay).unwrap_or_default(),
+                                }
+                            }
+                            (_, Some(delay)) => {
+                                log::warn!(
+                                    "failed to get usage data of {}: {}. retrying in {:?}",
+                                    account_id,
+                                    e,
+                                    delay
+                                );
+                                interval.reset_after(delay);
+
+                                PollState::Retrying {
+                                    attempt: backoff.attempts(),
+                                    max_attempts: retry.max_attempts,
+                                    retry_at: Timestamp::now()
+                                        + SignedDuration::try_from(delay).unwrap_or_default(),
+                                }
+                            }
+                            (_, None) => {
+                                log::warn!(
+                                    "failed to get usage data of {}: {}. retrying at the next poll",
+                                    account_id,
+                                    e
+                                );
+                                interval.reset();
+
+                                PollState::Offline
+                            }
+                        };
+
+                        handle_tracking
+                            .update(|tray: &mut AppTray| {
+                                if let Some(account) = tray.account_mut(&account_id) {
+                                    account.poll_state = poll_state;
+                                }
+                            })
+                            .await;
+                        publish_state(&handle_tracking).await;
+                        continue;
+                    }
+                };
+
+                backoff.reset();
+
+                next_reset = usage.next_reset();
+
+                let notifications = handle_tracking
+                    .update(|tray: &mut AppTray| {
+                        let label = tray.account_mut(&account_id)?.credentials.label();
+                        let language = tray.language();
+                        let mut notifications =
+                            tray.usage_notifications.check(&account_id, &label, &usage);
+
+                        let history = tray.history.clone();
+                        let account = tray.account_mut(&account_id)?;
+                        let updated_at = Timestamp::now();
+
+                        notifications.extend(
+                            account.forecast.update(&usage, updated_at).into_iter().map(
+                                |(key, period_label, run_out)| {
+                                    Notification::run_out(
+                                        &label,
+                                        &language.period_label(&key, &period_label),
+                                        &run_out,
+                                        language,
+                                    )
+                                },
+                            ),
+                        );
+                        let sample = history.map(|history| {
+                            (
+                                history,
+                                UsageSample::new(&account.credentials, &usage, updated_at),
+                            )
+                        });
+
+                        account.usage = Some(usage);
+                        account.updated_at = Some(updated_at);
+                        account.poll_state = PollState::Online;
+
+                        Some((notifications, tray.desktop_notifications(), sample))
+                    })
+                    .await
+                    .flatten();
+
+                if let Some((notifications, desktop, sample)) = notifications {
+                    if let Some((history, sample)) = sample {
+                        record_sample(&handle_tracking, history, sample).await;
+                    }
+
+                    if let Some(desktop) = desktop {
+                        desktop.send_all(&notifications).await;
+                    }
+                }
+
+                publish_state(&handle_tracking).await;
+            }
+        };
+
+        // Claude Code renews its credentials by itself, so its account is kept
+        // until the reload of its file restarts the tracking
+        if let Err(error) = &tracking_result
+            && credentials.source == CredentialSource::ClaudeCode
+        {
+            log::warn!(
+                "usage tracking of {} paused: {}. waiting for claude code to renew its credentials",
+                account_id,
+                error
+            );
+
+            handle_tracking
+                .update(|tray: &mut AppTray| {
+                    if let Some(account) = tray.account_mut(&account_id) {
+                        account.poll_state = PollState::WaitingForClaudeCode;
+                    }
+                })
+                .await;
+            publish_state(&handle_tracking).await;
+            return;
+        }
+
+        if let Err(error) = tracking_result {
+            log::error!("usage tracking of {} failed: {}", account_id, error);
+
+            // The account goes back to the logged out state. Its
+            // credentials are kept, so it is loaded again on restart
+            let notification = handle_tracking
+                .update(|tray: &mut AppTray| {
+                    let account = tray.remove_account(&account_id);
+
+                    tray.send(TrayMessage::StopUsageTracking(account_id.clone()));
+
+                    account.map(|account| {
+                        (
+                            Notification::auth_lost(&account.credentials.label(), tray.language()),
+                            tray.desktop_notifications(),
+                        )
+                    })
+                })
+                .await
+                .flatten();
+
+            if let Some((notification, Some(desktop))) = notification {
+                desktop.send_all(&[notification]).await;
+            }
+        }
+    });
+
+    Ok(tracking_task)
+}
+
+// Records a usage sample in the history and refreshes the summary of its account.
+// The database is written in a blocking thread, so the tray keeps running meanwhile.
+async fn record_sample(handle: &TrayHandle, history: UsageHistory, sample: UsageSample) {
+    let account_id = sample.account_id.clone();
+
+    let summary = tokio::task::spawn_blocking(move || {
+        history
+            .record(&sample)
+            .and_then(|_| history.summary(&sample.account_id, sample.recorded_at))
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("failed to record usage sample: {}", e)));
+
+    match summary {
+        Ok(summary) => {
+            handle
+                .update(|tray: &mut AppTray| {
+                    if let Some(account) = tray.account_mut(&account_id) {
+                        account.history = summary;
+                    }
+                })
+                .await;
+        }
+        Err(e) => log::error!("{}", e),
+    }
+}
+
+// Reads the usage of an account. When the token is rejected before its
+// expiration, the request is retried once with a refreshed token. A poll
+// is counted as failed once, only when its final result is an error.
+async fn poll_usage(
+    handle: &TrayHandle,
+    account_id: &str,
+    credentials: &mut ClaudeCredentials,
+) -> Result<ClaudeUsageResponse, ClaudeError> {
+    let result = match claude::get_usage(&credentials.access_token).await {
+        Err(ClaudeError::Unauthorized(e)) => {
+            log::warn!("usage request rejected: {}. refreshing access token", e);
+
+            match refresh_tracking_credentials(handle, credentials).await {
+                Ok(refreshed) => {
+                    *credentials = refreshed;
+                    claude::get_usage(&credentials.access_token).await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        result => result,
+    };
+
+    if result.is_err() {
+        count_poll_error(handle, account_id).await;
+    }
+
+    result
+}
+
+// Counts a failed usage poll of an account for the metrics endpoint.
+async fn count_poll_error(handle: &TrayHandle, account_id: &str) {
+    handle
+        .update(|tray: &mut AppTray| {
+            *tray.poll_errors.entry(account_id.to_string()).or_default() += 1;
+        })
+        .await;
+
+    publish_state(handle).await;
+}
+
+// Refreshes the credentials used by the tracking task and hands
+// the new ones to the tray.
+async fn refresh_tracking_credentials(
+    handle: &TrayHandle,
+    credentials: &ClaudeCredentials,
+) -> Result<ClaudeCredentials, ClaudeError> {
+    let refreshed = claude::refresh_credentials(credentials).await?;
+
+    let tray_credentials = refreshed.clone();
+    handle
+        .update(|tray: &mut AppTray| tray.upsert_account(tray_credentials))
+        .await;
+
+    Ok(refreshed)
+}
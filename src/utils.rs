@@ -1,3 +1,12 @@
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use log::trace;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+
+use crate::claude::ExtraUsage;
+use crate::forecast::RunOut;
+use crate::i18n::{Language, Text};
+
 // Extracts a parameters values from an URL
 pub fn extract_param_from_url(request: &str, param_name: &str) -> Result<String, String> {
     let search = format!("{}=", param_name);
@@ -10,3 +19,274 @@ pub fn extract_param_from_url(request: &str, param_name: &str) -> Result<String,
 
     Ok(param_part[..param_end].to_string())
 }
+
+// Watches a file, calling `on_change` every time it is written or removed. Its
+// directory must exist. The watcher stops when the returned value is dropped.
+pub fn watch_file(
+    path: &Path,
+    on_change: impl Fn() + Send + 'static,
+) -> Result<RecommendedWatcher, String> {
+    let dir = path
+        .parent()
+        .ok_or(format!("invalid file path {:?}", path))?;
+    let file_name = path
+        .file_name()
+        .ok_or(format!("invalid file path {:?}", path))?
+        .to_os_string();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+
+        // Editors and Claude Code usually replace the file, so the events
+        // of the whole directory are received and filtered
+        let is_watched_file = event
+            .paths
+            .iter()
+            .any(|path| path.file_name().is_some_and(|name| name == file_name));
+
+        if is_watched_file
+            && matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            )
+        {
+            on_change();
+        }
+    })
+    .map_err(|e| format!("failed to create watcher of {:?}: {}", path, e))?;
+
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("failed to watch {:?}: {}", dir, e))?;
+
+    trace!("watching {:?}", path);
+
+    Ok(watcher)
+}
+
+// Formats the time left until a usage period resets, followed
+// by the local reset time (e.g. "resets in 2h 13m (14:30)").
+pub fn format_reset(resets_at: Timestamp, language: Language) -> String {
+    let Some(countdown) = format_countdown(resets_at.duration_since(Timestamp::now())) else {
+        return language.text(Text::ResettingNow).into();
+    };
+
+    format!(
+        "{} {} ({})",
+        language.text(Text::ResetsIn),
+        countdown,
+        format_local_time(resets_at)
+    )
+}
+
+// Time left in its two largest units (e.g. "2h 13m"), `None` once it is over
+fn format_countdown(remaining: SignedDuration) -> Option<String> {
+    let remaining_secs = remaining.as_secs();
+
+    if remaining_secs <= 0 {
+        return None;
+    }
+
+    // Minutes are rounded up, so it never shows "0m" before the reset
+    let remaining_mins = (remaining_secs + 59) / 60;
+    let (days, hours, mins) = (
+        remaining_mins / (24 * 60),
+        remaining_mins / 60 % 24,
+        remaining_mins % 60,
+    );
+
+    Some(if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m", mins)
+    })
+}
+
+// Local time of a timestamp, with the weekday when it is not today (e.g. "Mon 14:30")
+pub fn format_local_time(timestamp: Timestamp) -> String {
+    let time_zone = TimeZone::system();
+    let local_time = timestamp.to_zoned(time_zone.clone());
+
+    if local_time.date() == Timestamp::now().to_zoned(time_zone).date() {
+        local_time.strftime("%H:%M").to_string()
+    } else {
+        local_time.strftime("%a %H:%M").to_string()
+    }
+}
+
+// Local time of a timestamp with seconds (e.g. "14:30:05")
+pub fn format_local_clock(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime("%H:%M:%S")
+        .to_string()
+}
+
+// Time passed since a timestamp, in its two largest units (e.g. "2h 13m")
+pub fn format_age(since: Timestamp) -> String {
+    let secs = Timestamp::now().duration_since(since).as_secs().max(0);
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m", mins)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// Projected run out of a usage period and its reset
+// (e.g. "on pace to run out at 15:10, resets 16:00")
+pub fn format_run_out(run_out: &RunOut, language: Language) -> String {
+    format!(
+        "{} {}, {} {}",
+        language.text(Text::OnPaceToRunOut),
+        format_local_time(run_out.runs_out_at),
+        language.text(Text::Resets),
+        format_local_time(run_out.resets_at)
+    )
+}
+
+// Usage period with its utilization and reset time, in a single line
+// (e.g. "Current session: 23%, resets in 2h 13m (14:30)")
+pub fn format_period(
+    name: &str,
+    utilization: f32,
+    resets_at: Option<Timestamp>,
+    language: Language,
+) -> String {
+    match resets_at {
+        Some(resets_at) => format!(
+            "{}: {}%, {}",
+            name,
+            utilization,
+            format_reset(resets_at, language)
+        ),
+        None => format!("{}: {}%", name, utilization),
+    }
+}
+
+// Used and monthly credits of the extra usage, if it is enabled
+pub fn format_extra_usage(extra_usage: &ExtraUsage, language: Language) -> Option<String> {
+    if !extra_usage.is_enabled {
+        return None;
+    }
+
+    let used_credits = extra_usage.used_credits.unwrap_or_default();
+    let credits = language.text(Text::Credits);
+
+    Some(match (extra_usage.monthly_limit, extra_usage.utilization) {
+        (Some(limit), Some(utilization)) => {
+            format!("{}/{} {} ({}%)", used_credits, limit, credits, utilization)
+        }
+        (Some(limit), None) => format!("{}/{} {}", used_credits, limit, credits),
+        (None, _) => format!("{} {}", used_credits, language.text(Text::CreditsUsed)),
+    })
+}
+
+// Text chart of utilization percentages, with a dot where there are no samples
+pub fn format_sparkline(values: &[Option<f32>]) -> String {
+    const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    values
+        .iter()
+        .map(|value| match value {
+            Some(utilization) => {
+                LEVELS[(utilization.clamp(0.0, 100.0) / 100.0 * 7.0).round() as usize]
+            }
+            None => '·',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // Client and server sides of a peer-to-peer D-Bus connection, used to run
+    // stand-in services. The server keeps serving its objects while it is alive.
+    pub async fn p2p_connections() -> (zbus::Connection, zbus::Connection) {
+        let (client, server) = tokio::net::UnixStream::pair().unwrap();
+
+        let server = zbus::connection::Builder::unix_stream(server)
+            .server(zbus::Guid::generate())
+            .unwrap()
+            .p2p()
+            .build();
+        let client = zbus::connection::Builder::unix_stream(client).p2p().build();
+
+        tokio::try_join!(client, server).unwrap()
+    }
+
+    #[test]
+    fn format_countdown_boundaries() {
+        let countdown = |secs| format_countdown(SignedDuration::from_secs(secs));
+
+        assert_eq!(countdown(-1), None);
+        assert_eq!(countdown(0), None);
+        // Minutes are rounded up
+        assert_eq!(countdown(1).as_deref(), Some("1m"));
+        assert_eq!(countdown(60).as_deref(), Some("1m"));
+        assert_eq!(countdown(61).as_deref(), Some("2m"));
+        assert_eq!(countdown(59 * 60 + 1).as_deref(), Some("1h 0m"));
+        assert_eq!(countdown(2 * 3600 + 13 * 60).as_deref(), Some("2h 13m"));
+        assert_eq!(countdown(24 * 3600 - 1).as_deref(), Some("1d 0h"));
+        assert_eq!(countdown(25 * 3600).as_deref(), Some("1d 1h"));
+    }
+
+    #[test]
+    fn format_reset_after_the_reset() {
+        let past = Timestamp::now() - SignedDuration::from_secs(1);
+
+        assert_eq!(format_reset(past, Language::English), "resetting now");
+        assert!(
+            format_reset(
+                Timestamp::now() + SignedDuration::from_mins(90),
+                Language::English
+            )
+            .starts_with("resets in 1h 30m (")
+        );
+    }
+
+    #[test]
+    fn format_sparkline_levels() {
+        assert_eq!(
+            format_sparkline(&[Some(0.0), Some(50.0), Some(100.0), None]),
+            "▁▅█·"
+        );
+        // Values out of range are clamped
+        assert_eq!(format_sparkline(&[Some(-5.0), Some(150.0)]), "▁█");
+        assert_eq!(format_sparkline(&[]), "");
+    }
+
+    #[test]
+    fn watch_file_ignores_other_files() {
+        let dir =
+            std::env::temp_dir().join(format!("claude-tray-test-{}-watch", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (sender, changes) = mpsc::channel();
+        let _watcher = watch_file(&dir.join("watched.json"), move || {
+            let _ = sender.send(());
+        })
+        .unwrap();
+
+        std::fs::write(dir.join("other.json"), "{}").unwrap();
+        assert!(changes.recv_timeout(Duration::from_millis(500)).is_err());
+
+        std::fs::write(dir.join("watched.json"), "{}").unwrap();
+        assert!(changes.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}